@@ -0,0 +1,35 @@
+use {
+    criterion::{criterion_group, criterion_main, BatchSize, Criterion},
+    sprint::*,
+};
+
+/// Compare running a 10k-command batch by reference and by move, without spawning, i.e. only the
+/// overhead per command
+fn batch_10k(c: &mut Criterion) {
+    let shell = Shell {
+        print: false,
+        dry_run: true,
+        ..Default::default()
+    };
+
+    let commands = (0..10_000)
+        .map(|i| Command {
+            stdout: Pipe::string(),
+            ..Command::new(&format!("true {i} {}", "x".repeat(1_000)))
+        })
+        .collect::<Vec<_>>();
+
+    let mut group = c.benchmark_group("batch_10k");
+    group.bench_function("run", |b| b.iter(|| shell.run(&commands)));
+    group.bench_function("run_owned", |b| {
+        b.iter_batched(
+            || commands.clone(),
+            |commands| shell.run_owned(commands),
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+criterion_group!(benches, batch_10k);
+criterion_main!(benches);
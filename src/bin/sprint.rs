@@ -1,18 +1,22 @@
 use {
-    anstream::println,
-    anyhow::Result,
-    clap::{builder::Styles, Parser},
+    anstream::print,
+    anyhow::{anyhow, Result},
+    clap::{builder::Styles, parser::ValueSource, CommandFactory, FromArgMatches, Parser},
+    ignore::gitignore::{Gitignore, GitignoreBuilder},
     ignore_check::Ignore,
     notify::{
         event::{AccessKind, AccessMode},
         Event, EventKind, RecursiveMode, Watcher,
     },
+    owo_colors::Style,
+    serde::{Deserialize, Serialize},
     sprint::*,
     std::{
         collections::BTreeMap,
         path::{Path, PathBuf},
+        sync::{mpsc::Receiver, Arc, Mutex},
         thread::sleep,
-        time::Duration,
+        time::{Duration, Instant},
     },
 };
 
@@ -33,7 +37,13 @@ struct Cli {
     arguments: Vec<String>,
 
     /// Shell
-    #[arg(short, long, value_name = "STRING", default_value = "sh -c")]
+    #[arg(
+        short,
+        long,
+        value_name = "STRING",
+        env = "SPRINT_SHELL",
+        default_value = "sh -c"
+    )]
     shell: String,
 
     /// Fence
@@ -48,43 +58,191 @@ struct Cli {
     #[arg(short, long, value_name = "STRING", default_value = "$ ")]
     prompt: String,
 
+    /// Fence style, e.g. `#555555` or `bright-black+dim`
+    #[arg(long, value_name = "STYLE", value_parser = parse_style)]
+    fence_style: Option<Style>,
+
+    /// Info style
+    #[arg(long, value_name = "STYLE", value_parser = parse_style)]
+    info_style: Option<Style>,
+
+    /// Prompt style
+    #[arg(long, value_name = "STYLE", value_parser = parse_style)]
+    prompt_style: Option<Style>,
+
+    /// Command style, e.g. `#00ffff+bold`
+    #[arg(long, value_name = "STYLE", value_parser = parse_style)]
+    command_style: Option<Style>,
+
+    /// Error style
+    #[arg(long, value_name = "STYLE", value_parser = parse_style)]
+    error_style: Option<Style>,
+
     /// Watch files/directories and rerun command on change; see also `-d` option
     #[arg(short, long, value_name = "PATH")]
     watch: Vec<PathBuf>,
 
-    /// Debounce; used only with `-w`
-    #[arg(short, long, value_name = "SECONDS", default_value = "5.0")]
-    debounce: f32,
+    /// Run the command(s) once instead of watching and exit with the code of the first failed
+    /// command or else the last command
+    #[arg(long, requires = "arguments")]
+    once: bool,
+
+    /// Run a command after the watched command succeeds; used only with `-w`
+    #[arg(long, value_name = "STRING")]
+    on_success: Option<String>,
+
+    /// Run a command after the watched command fails; used only with `-w`
+    #[arg(long, value_name = "STRING")]
+    on_failure: Option<String>,
+
+    /// Replace `{}` or `$FILE` in the command with the first changed file; used only with `-w`
+    #[arg(long)]
+    substitute: bool,
+
+    /// Restart the command if it fails, backing off per the debounce; used only with `-w`
+    #[arg(long)]
+    restart: bool,
+
+    /// Only rerun on changes to files with these extension(s), e.g. `rs,toml`; used only with `-w`
+    #[arg(long, value_name = "EXT", value_delimiter = ',')]
+    ext: Vec<String>,
+
+    /// Ignore changes to paths matching a gitignore-style glob, e.g. `*.tmp` or `generated/`; used
+    /// only with `-w`
+    #[arg(long, value_name = "GLOB")]
+    ignore: Vec<String>,
+
+    /// Debounce in seconds or with a unit, e.g. `1.5`, `200ms`, `2s`; changes within the debounce
+    /// after the first cause a single rerun; used only with `-w`
+    #[arg(
+        short,
+        long,
+        value_name = "DURATION",
+        default_value = "1s",
+        value_parser = parse_duration
+    )]
+    debounce: Duration,
+
+    /// Keep running the remaining commands after a command fails and print a summary; in
+    /// interactive mode, show the exit code of a failed command in the prompt instead of exiting
+    #[arg(short, long)]
+    keep_going: bool,
+
+    /// Show progress of the commands
+    #[cfg(feature = "indicatif")]
+    #[arg(long)]
+    progress: bool,
+
+    /// Print the program and arguments of each command before running it
+    #[arg(short, long)]
+    verbose: bool,
+
+    /// Print fences, info, and prompts to stderr instead of stdout
+    #[arg(long)]
+    decorations_to_stderr: bool,
+
+    /// Make file paths in watch mode notifications clickable in supporting terminals
+    #[arg(long)]
+    hyperlinks: bool,
 
     /// Force enable/disable terminal colors
     #[arg(short = 'C', long, default_value = "auto")]
     color: ColorOverride,
+
+    /// Print the effective configuration and exit
+    #[arg(long)]
+    print_config: bool,
 }
 
 fn main() -> Result<()> {
-    let cli = Cli::parse();
-
-    cli.color.init();
+    let matches = Cli::command().get_matches();
+    let mut cli = Cli::from_arg_matches(&matches)?;
+
+    // Options given on the command line or via the environment override the config file
+    let config = read_config()?;
+    let default = |key| {
+        matches!(
+            matches.value_source(key),
+            None | Some(ValueSource::DefaultValue)
+        )
+    };
+    for (key, field, value) in [
+        ("shell", &mut cli.shell, config.shell),
+        ("fence", &mut cli.fence, config.fence),
+        ("info", &mut cli.info, config.info),
+        ("prompt", &mut cli.prompt, config.prompt),
+    ] {
+        if let Some(value) = value.filter(|_x| default(key)) {
+            *field = value;
+        }
+    }
+    if let Some(debounce) = config.debounce.filter(|_x| default("debounce")) {
+        cli.debounce = debounce.duration()?;
+    }
 
-    let shell = Shell {
+    let mut shell = Shell {
         shell: Some(cli.shell.clone()),
         fence: cli.fence.clone(),
         info: cli.info.clone(),
         prompt: cli.prompt.clone(),
+        keep_going: cli.keep_going,
+        #[cfg(feature = "indicatif")]
+        progress: cli.progress,
+        verbose: cli.verbose,
+        decorations_to_stderr: cli.decorations_to_stderr,
+        hyperlinks: cli.hyperlinks,
+        color: cli.color.clone(),
         ..Default::default()
     };
 
+    for (field, option, value) in [
+        (&mut shell.fence_style, cli.fence_style, config.fence_style),
+        (&mut shell.info_style, cli.info_style, config.info_style),
+        (
+            &mut shell.prompt_style,
+            cli.prompt_style,
+            config.prompt_style,
+        ),
+        (
+            &mut shell.command_style,
+            cli.command_style,
+            config.command_style,
+        ),
+        (&mut shell.error_style, cli.error_style, config.error_style),
+    ] {
+        if let Some(option) = option {
+            *field = option;
+        } else if let Some(value) = value {
+            *field = style(&value)?;
+        }
+    }
+
+    if cli.print_config {
+        print_config(&shell, cli.debounce);
+        return Ok(());
+    }
+
     let no_arguments = cli.arguments.is_empty();
-    let no_watch = cli.watch.is_empty();
+    let no_watch = cli.watch.is_empty() || cli.once;
 
     if no_arguments && no_watch {
         // Run interactively
 
+        // The command gets the interrupt too since it shares the terminal's process group
+        on_interrupt({
+            let shell = shell.clone();
+            move || {
+                shell.interactive_prompt_reset();
+                shell.emit(format_args!("\n"));
+                shell.print_close();
+                std::process::exit(130);
+            }
+        });
+
         let stdin = std::io::stdin();
         shell.interactive_prompt(false);
         loop {
-            let mut command = String::new();
-            if stdin.read_line(&mut command).is_ok() {
+            if let Ok(command) = read_command(&stdin) {
                 shell.interactive_prompt_reset();
 
                 if command.is_empty() {
@@ -92,14 +250,27 @@ fn main() -> Result<()> {
                     break;
                 }
 
-                let result = shell.core(&Command::new(command.trim()));
+                if let Some(code) = builtin_exit(&command) {
+                    std::process::exit(code);
+                }
 
-                if let Some(code) = &result.code {
-                    if !result.codes.contains(code) {
-                        std::process::exit(*code);
+                let result = match shell.core(inherit(command.trim())) {
+                    Ok(result) => result,
+                    Err(e) => {
+                        eprintln!("ERROR: {e}");
+                        shell.interactive_prompt(true);
+                        continue;
                     }
-                } else {
-                    std::process::exit(1);
+                };
+
+                if shell.keep_going {
+                    // Show the exit code in the prompt instead of exiting
+                    shell.interactive_prompt_result(&result);
+                    continue;
+                }
+
+                if !result.is_success(result.code) {
+                    std::process::exit(result.code.unwrap_or(1));
                 }
 
                 shell.interactive_prompt(true);
@@ -110,95 +281,28 @@ fn main() -> Result<()> {
     } else if no_watch {
         // Run given commands / files
 
-        let results = shell.run(
-            &cli.arguments
-                .iter()
-                .map(|x| Command::new(x))
-                .collect::<Vec<_>>(),
-        );
+        let results = shell.run(&cli.arguments.iter().map(|x| inherit(x)).collect::<Vec<_>>());
 
-        // Exit with the code of the last command
-        std::process::exit(results.last().unwrap().code.unwrap_or(1));
+        // Exit with the code of the first failed command or else the last command
+        let result = results
+            .iter()
+            .find(|x| !x.succeeded())
+            .or(results.last())
+            .unwrap();
+        std::process::exit(result.code.unwrap_or(1));
     } else if no_arguments {
         // Watch, but no commands...
 
-        // Get watched directories & files
-        let (dirs, mut hashes) = watched(&cli.watch);
-        let ignored = Ignore::default();
-        let pwd = std::env::current_dir().unwrap();
-
-        let debounce = std::time::Duration::from_secs_f32(cli.debounce);
-        let mut ts = std::time::Instant::now();
-
-        let mut watcher =
-            notify::recommended_watcher(move |res: notify::Result<Event>| match res {
-                Ok(event) => {
-                    let now = std::time::Instant::now();
-                    match event.kind {
-                        EventKind::Create(_) | EventKind::Remove(_) => {
-                            // Created or deleted a file/directory
-                            'outer: for path in event
-                                .paths
-                                .iter()
-                                .map(|x| x.strip_prefix(&pwd).unwrap().to_path_buf())
-                                .filter(|x| not_ignored(x, &ignored, &dirs, &hashes))
-                            {
-                                if now - ts > debounce {
-                                    println!(
-                                        "* {}: `{}`",
-                                        match event.kind {
-                                            EventKind::Create(_) => "Created",
-                                            EventKind::Remove(_) => "Removed",
-                                            _ => unreachable!(),
-                                        },
-                                        path.display(),
-                                    );
-                                    ts = now;
-                                    break 'outer;
-                                }
-                            }
-                        }
-                        EventKind::Access(AccessKind::Close(AccessMode::Write)) => {
-                            // Wrote a file
-                            let mut not_restarted = true;
-                            let paths = event
-                                .paths
-                                .iter()
-                                .map(|x| x.strip_prefix(&pwd).unwrap().to_path_buf())
-                                .filter(|x| not_ignored(x, &ignored, &dirs, &hashes))
-                                .collect::<Vec<_>>();
-                            for path in paths {
-                                if let Some(h1) = hashes.get(&path) {
-                                    let h2 = hash(&path);
-                                    if h2 != *h1 {
-                                        // File changed...
-
-                                        // Update the hash
-                                        hashes.insert(path.clone(), h2);
-
-                                        if not_restarted && now - ts > debounce {
-                                            println!("* Modified: `{}`", path.display());
-                                            ts = now;
-                                            not_restarted = false;
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                        _ => {}
-                    }
-                }
-                Err(_e) => {
-                    std::process::exit(1);
-                }
-            })?;
+        let (_watcher, rx) = watch(&cli)?;
 
-        for path in &cli.watch {
-            watcher.watch(path, RecursiveMode::Recursive)?;
-        }
-
-        loop {
-            sleep(Duration::from_secs_f32(0.25));
+        while let Some(changes) = collect(&rx, cli.debounce) {
+            for change in changes {
+                shell.emitln(format_args!(
+                    "* {}: `{}`",
+                    change.kind,
+                    shell.hyperlink(&change.path)
+                ));
+            }
         }
     } else {
         // Watch
@@ -209,116 +313,457 @@ fn main() -> Result<()> {
             std::process::exit(1);
         }
 
-        // Run the command in a child process
-        let command = Command::new(&cli.arguments[0]);
-        let (mut process, mut ts) = run(&shell, &command);
-
-        // Get watched directories & files
-        let (dirs, mut hashes) = watched(&cli.watch);
-        let ignored = Ignore::default();
-        let pwd = std::env::current_dir().unwrap();
-
-        let debounce = std::time::Duration::from_secs_f32(cli.debounce);
-
-        let mut watcher =
-            notify::recommended_watcher(move |res: notify::Result<Event>| match res {
-                Ok(event) => {
-                    let now = std::time::Instant::now();
-                    match event.kind {
-                        EventKind::Create(_) | EventKind::Remove(_) => {
-                            // Created or deleted a file/directory
-                            for path in event
-                                .paths
-                                .iter()
-                                .map(|x| x.strip_prefix(&pwd).unwrap().to_path_buf())
-                                .filter(|x| not_ignored(x, &ignored, &dirs, &hashes))
-                            {
-                                // In a watched directory...
-
-                                if now - ts > debounce {
-                                    // Kill the command (if still running)
-                                    if let Ok(None) = process.try_wait() {
-                                        process.kill().expect("kill process");
-                                    }
-                                    shell.print_fence(2);
-
-                                    println!(
-                                        "* {}: `{}`\n",
-                                        match event.kind {
-                                            EventKind::Create(_) => "Created",
-                                            EventKind::Remove(_) => "Removed",
-                                            _ => unreachable!(),
-                                        },
-                                        path.display(),
-                                    );
-
-                                    // Run the command again
-                                    (process, ts) = run(&shell, &command);
-
-                                    break;
-                                }
-                            }
-                        }
-                        EventKind::Access(AccessKind::Close(AccessMode::Write)) => {
-                            // Wrote a file
-                            let mut not_restarted = true;
-                            let paths = event
-                                .paths
-                                .iter()
-                                .map(|x| x.strip_prefix(&pwd).unwrap().to_path_buf())
-                                .filter(|x| not_ignored(x, &ignored, &dirs, &hashes))
-                                .collect::<Vec<_>>();
-                            for path in paths {
-                                if let Some(h1) = hashes.get(&path) {
-                                    let h2 = hash(&path);
-                                    if h2 != *h1 {
-                                        // File changed...
-
-                                        // Update the hash
-                                        hashes.insert(path.clone(), h2);
-
-                                        if not_restarted && now - ts > debounce {
-                                            // Kill the command (if still running)
-                                            if let Ok(None) = process.try_wait() {
-                                                process.kill().expect("kill process");
-                                            }
-                                            shell.print_fence(2);
-
-                                            println!("* Modified: `{}`\n", path.display());
-
-                                            // Run the command again
-                                            (process, ts) = run(&shell, &command);
-                                            not_restarted = false;
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                        _ => {}
-                    }
+        // Run the command in a child process in its own process group
+        let shell = Shell {
+            process_group: true,
+            ..shell
+        };
+        let command = inherit(&cli.arguments[0]);
+        let command_for = |path: Option<&Path>| {
+            if cli.substitute {
+                substitute(&command, path)
+            } else {
+                command.clone()
+            }
+        };
+        let process = run(&shell, &command_for(None))?;
+        let process = Arc::new(Mutex::new(process));
+
+        // Kill the command since its own process group does not get the interrupt
+        on_interrupt({
+            let process = process.clone();
+            let shell = shell.clone();
+            move || {
+                let mut watched = process.lock().expect("lock");
+                watched.process.kill().ok();
+                shell.emit(format_args!("\n"));
+                if !watched.done {
+                    shell.print_close();
                 }
-                Err(_e) => {
-                    std::process::exit(1);
+                std::process::exit(130);
+            }
+        });
+
+        if cli.on_success.is_some() || cli.on_failure.is_some() || cli.restart {
+            // Wait for the command to exit, run the hook for its outcome, and restart it if it
+            // failed
+            let process = process.clone();
+            let shell = shell.clone();
+            let command = command.clone();
+            let (on_success, on_failure) = (cli.on_success.clone(), cli.on_failure.clone());
+            let (restart, debounce) = (cli.restart, cli.debounce);
+            std::thread::spawn(move || loop {
+                sleep(Duration::from_secs_f32(0.1));
+                let mut watched = process.lock().expect("lock");
+                if watched.done {
+                    continue;
                 }
-            })?;
+                if let Ok(Some(status)) = watched.process.child.try_wait() {
+                    watched.done = true;
+                    shell.print_close();
+                    let success = command.is_success(status.code());
+                    let restarts = watched.restarts;
+
+                    // Release the process so a change or an interrupt is not blocked by the hook
+                    drop(watched);
+
+                    let hook = if success { &on_success } else { &on_failure };
+                    if let Some(hook) = hook {
+                        shell.run(&[inherit(hook)]);
+                    }
 
-        for path in &cli.watch {
-            watcher.watch(path, RecursiveMode::Recursive)?;
+                    if restart && !success {
+                        // Back off exponentially to avoid a crash loop
+                        sleep(debounce * 2u32.pow(restarts.min(5)));
+
+                        let mut watched = process.lock().expect("lock");
+                        if watched.done {
+                            // Not restarted by a change in the meantime
+                            shell.emitln(format_args!(
+                                "* Restarted: exit code `{}`\n",
+                                status
+                                    .code()
+                                    .map(|x| x.to_string())
+                                    .unwrap_or_else(|| String::from("none")),
+                            ));
+                            // Rerun the same command including the changed file, if any
+                            let command = watched.process.command.clone();
+                            let restarted = run(&shell, &command).expect("run command");
+                            *watched = Watched {
+                                restarts: restarts + 1,
+                                ..restarted
+                            };
+                        }
+                    }
+                }
+            });
         }
 
-        loop {
-            sleep(Duration::from_secs_f32(0.25));
+        let (_watcher, rx) = watch(&cli)?;
+
+        while let Some(changes) = collect(&rx, cli.debounce) {
+            // Kill the command (if still running)
+            let mut watched = process.lock().expect("lock");
+            watched.process.kill().expect("kill process");
+            if !watched.done {
+                shell.print_close();
+            }
+
+            for change in &changes {
+                shell.emitln(format_args!(
+                    "* {}: `{}`",
+                    change.kind,
+                    shell.hyperlink(&change.path),
+                ));
+            }
+            shell.emit(format_args!("\n"));
+
+            // Run the command again
+            *watched = run(&shell, &command_for(Some(&changes[0].path))).expect("run command");
         }
     }
 
     Ok(())
 }
 
-fn run(shell: &Shell, command: &Command) -> (std::process::Child, std::time::Instant) {
+/// Parse a duration in seconds, milliseconds (`ms` suffix), or seconds (`s` suffix)
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    let (n, scale) = if let Some(n) = s.strip_suffix("ms") {
+        (n, 0.001)
+    } else if let Some(n) = s.strip_suffix('s') {
+        (n, 1.0)
+    } else {
+        (s, 1.0)
+    };
+    n.trim()
+        .parse::<f64>()
+        .ok()
+        .and_then(|x| Duration::try_from_secs_f64(x * scale).ok())
+        .ok_or_else(|| format!("Invalid duration: {s:?}!"))
+}
+
+/// Parse a style spec; see [`style`]
+fn parse_style(s: &str) -> Result<Style, String> {
+    style(s).map_err(|e| e.to_string())
+}
+
+/// Read a command from stdin, joining lines that end with a backslash
+///
+/// Lines pasted into a terminal at once are also joined so a pasted block is run as one command
+/// instead of line by line.
+fn read_command(stdin: &std::io::Stdin) -> std::io::Result<String> {
+    let mut r = String::new();
+    let mut pasted = false;
+    loop {
+        let mut line = String::new();
+        if stdin.read_line(&mut line)? == 0 {
+            // Control + D
+            return Ok(r);
+        }
+
+        let trimmed = line.trim_end_matches(['\n', '\r']);
+        let backslashes = trimmed.len() - trimmed.trim_end_matches('\\').len();
+        if backslashes % 2 == 1 {
+            // Continuation; remove the backslash-newline and read the next line
+            r.push_str(&trimmed[..trimmed.len() - 1]);
+        } else if pending(stdin) {
+            // More lines were pasted with this one
+            r.push_str(&line);
+            pasted = true;
+        } else {
+            r.push_str(&line);
+            if pasted {
+                eprintln!("WARNING: Pasted input has multiple lines; running them as one command!");
+            }
+            return Ok(r);
+        }
+    }
+}
+
+/// True if stdin is a terminal and more input is available without waiting for the user
+#[cfg(unix)]
+fn pending(stdin: &std::io::Stdin) -> bool {
+    use std::{io::IsTerminal, os::fd::AsRawFd};
+
+    if !stdin.is_terminal() {
+        return false;
+    }
+
+    let mut fds = libc::pollfd {
+        fd: stdin.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    unsafe { libc::poll(&mut fds, 1, 10) > 0 }
+}
+
+#[cfg(not(unix))]
+fn pending(_stdin: &std::io::Stdin) -> bool {
+    false
+}
+
+/// Get the exit code if the command is the `exit` or `quit` builtin with an optional code
+fn builtin_exit(command: &str) -> Option<i32> {
+    let mut words = command.split_whitespace();
+    match (words.next()?, words.next(), words.next()) {
+        ("exit" | "quit", None, None) => Some(0),
+        ("exit" | "quit", Some(code), None) => code.parse().ok(),
+        _ => None,
+    }
+}
+
+/// Create a command that shares sprint's terminal so interactive commands work
+fn inherit(command: &str) -> Command {
+    Command {
+        stdin: Pipe::Inherit,
+        stdout: Pipe::Inherit,
+        stderr: Pipe::Inherit,
+        ..Command::new(command)
+    }
+}
+
+/**
+Replace the `{}` and `$FILE` placeholders in a watched command with the quoted changed file
+
+The placeholders are removed if there is no changed file, i.e. on the first run.
+Use `{{}}` for a literal `{}` and `\$FILE` for a literal `$FILE`.
+*/
+fn substitute(command: &Command, path: Option<&Path>) -> Command {
+    let path = path
+        .map(|x| quote(&x.to_string_lossy()))
+        .unwrap_or_default();
+
+    let mut r = String::new();
+    let mut rest = command.command.as_str();
+    while let Some(c) = rest.chars().next() {
+        if let Some(after) = rest.strip_prefix("{{}}") {
+            r.push_str("{}");
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix("\\$") {
+            r.push_str("\\$");
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix("{}").or_else(|| {
+            rest.strip_prefix("$FILE")
+                .filter(|x| !x.starts_with(|c: char| c.is_ascii_alphanumeric() || c == '_'))
+        }) {
+            r.push_str(&path);
+            rest = after;
+        } else {
+            r.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+
+    Command {
+        command: r,
+        ..command.clone()
+    }
+}
+
+/// Run `f` on a background thread when the user presses Control + C
+fn on_interrupt(f: impl FnMut() + Send + 'static) {
+    ctrlc::set_handler(f).expect("set interrupt handler");
+}
+
+/// Print the settings in effect after merging defaults, the config file, environment variables, and
+/// options
+///
+/// The output is a valid config file; see [`read_config`].
+fn print_config(shell: &Shell, debounce: Duration) {
+    let config = Config {
+        shell: shell.shell.clone(),
+        fence: Some(shell.fence.clone()),
+        info: Some(shell.info.clone()),
+        prompt: Some(shell.prompt.clone()),
+        debounce: Some(Debounce::String(format!("{debounce:?}"))),
+        fence_style: Some(style_to_string(&shell.fence_style)),
+        info_style: Some(style_to_string(&shell.info_style)),
+        prompt_style: Some(style_to_string(&shell.prompt_style)),
+        command_style: Some(style_to_string(&shell.command_style)),
+        error_style: Some(style_to_string(&shell.error_style)),
+    };
+    print!("{}", toml::to_string(&config).expect("serialize config"));
+}
+
+/// Path of the config file: `SPRINT_CONFIG` if set, otherwise `sprint/config.toml` in
+/// `XDG_CONFIG_HOME` or `~/.config`
+fn config_path() -> Option<PathBuf> {
+    if let Some(path) = std::env::var_os("SPRINT_CONFIG") {
+        return Some(PathBuf::from(path));
+    }
+
+    let dir = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|x| !x.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|x| PathBuf::from(x).join(".config")))?;
+
+    Some(dir.join("sprint").join("config.toml"))
+}
+
+/// Settings in the config file; see [`read_config`]
+#[derive(Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct Config {
+    shell: Option<String>,
+    fence: Option<String>,
+    info: Option<String>,
+    prompt: Option<String>,
+    debounce: Option<Debounce>,
+    fence_style: Option<String>,
+    info_style: Option<String>,
+    prompt_style: Option<String>,
+    command_style: Option<String>,
+    error_style: Option<String>,
+}
+
+/// Debounce in the config file: seconds as a number or a string like the `-d` option
+#[derive(Deserialize, Serialize)]
+#[serde(untagged)]
+enum Debounce {
+    Seconds(f64),
+    String(String),
+}
+
+impl Debounce {
+    fn duration(&self) -> Result<Duration> {
+        match self {
+            Debounce::Seconds(x) => parse_duration(&x.to_string()),
+            Debounce::String(s) => parse_duration(s),
+        }
+        .map_err(|e| anyhow!(e))
+    }
+}
+
+/// Read the config file if it exists; see [`config_path`]
+///
+/// It is an error if `SPRINT_CONFIG` is set but the file does not exist.
+fn read_config() -> Result<Config> {
+    let Some(path) = config_path() else {
+        return Ok(Config::default());
+    };
+
+    let s = match std::fs::read_to_string(&path) {
+        Ok(s) => s,
+        Err(e)
+            if e.kind() == std::io::ErrorKind::NotFound
+                && std::env::var_os("SPRINT_CONFIG").is_none() =>
+        {
+            return Ok(Config::default());
+        }
+        Err(e) => return Err(anyhow!("Failed to read config file {path:?}: {e}!")),
+    };
+
+    toml::from_str(&s).map_err(|e| anyhow!("Invalid config file {path:?}: {e}"))
+}
+
+/// Watched command and whether its exit has been handled
+struct Watched {
+    process: RunningCommand,
+    done: bool,
+    /// Number of consecutive restarts after the command failed
+    restarts: u32,
+}
+
+fn run(shell: &Shell, command: &Command) -> Result<Watched> {
     shell.interactive_prompt(false);
-    println!("{}", command.command);
+    shell.emitln(format_args!("{}", command.command));
     shell.interactive_prompt_reset();
-    (shell.run1_async(command), std::time::Instant::now())
+    Ok(Watched {
+        process: shell.spawn(command)?,
+        done: false,
+        restarts: 0,
+    })
+}
+
+/// Change to a watched path
+struct Change {
+    /// `Created`, `Removed`, or `Modified`
+    kind: &'static str,
+    path: PathBuf,
+}
+
+/// Watch the `-w` paths and send each change to a path that is not ignored
+fn watch(cli: &Cli) -> Result<(notify::RecommendedWatcher, Receiver<Change>)> {
+    // Get watched directories & files
+    let (dirs, mut hashes) = watched(&cli.watch);
+    let ignored = Ignore::default();
+    let patterns = ignore_patterns(&cli.ignore)?;
+    let ext = cli.ext.clone();
+    let pwd = std::env::current_dir()?;
+
+    let (tx, rx) = std::sync::mpsc::channel();
+
+    let mut watcher = notify::recommended_watcher(move |res: notify::Result<Event>| match res {
+        Ok(event) => {
+            let paths = event
+                .paths
+                .iter()
+                .map(|x| x.strip_prefix(&pwd).unwrap().to_path_buf())
+                .filter(|x| not_ignored(x, &ignored, &patterns, &ext));
+            match event.kind {
+                EventKind::Create(_) | EventKind::Remove(_) => {
+                    // Created or deleted a file/directory in a watched directory
+                    let kind = match event.kind {
+                        EventKind::Create(_) => "Created",
+                        _ => "Removed",
+                    };
+                    for path in paths.filter(|x| !dirs.contains(x) && !hashes.contains_key(x)) {
+                        tx.send(Change { kind, path }).ok();
+                    }
+                }
+                EventKind::Access(AccessKind::Close(AccessMode::Write)) => {
+                    // Wrote a file
+                    for path in paths {
+                        if let Some(h1) = hashes.get(&path) {
+                            let h2 = hash(&path);
+                            if h2 != *h1 {
+                                // File changed; update the hash
+                                hashes.insert(path.clone(), h2);
+                                tx.send(Change {
+                                    kind: "Modified",
+                                    path,
+                                })
+                                .ok();
+                            }
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        Err(_e) => {
+            std::process::exit(1);
+        }
+    })?;
+
+    for path in &cli.watch {
+        watcher.watch(path, RecursiveMode::Recursive)?;
+    }
+
+    Ok((watcher, rx))
+}
+
+/// Wait for a change and collect it with the changes within the debounce after it, so bulk edits
+/// cause a single rerun
+///
+/// Each path is listed once, in the order it first changed, with its last change.
+fn collect(rx: &Receiver<Change>, debounce: Duration) -> Option<Vec<Change>> {
+    let first = rx.recv().ok()?;
+    let deadline = Instant::now() + debounce;
+
+    let mut r = vec![first];
+    while let Some(timeout) = deadline.checked_duration_since(Instant::now()) {
+        let Ok(change) = rx.recv_timeout(timeout) else {
+            break;
+        };
+        if let Some(previous) = r.iter_mut().find(|x| x.path == change.path) {
+            previous.kind = change.kind;
+        } else {
+            r.push(change);
+        }
+    }
+
+    Some(r)
 }
 
 fn watched(args: &[PathBuf]) -> (Vec<PathBuf>, BTreeMap<PathBuf, String>) {
@@ -355,14 +800,26 @@ fn watched(args: &[PathBuf]) -> (Vec<PathBuf>, BTreeMap<PathBuf, String>) {
     (dirs, hashes)
 }
 
-fn not_ignored(
-    path: &Path,
-    ignored: &Ignore,
-    dirs: &[PathBuf],
-    hashes: &BTreeMap<PathBuf, String>,
-) -> bool {
+/// Compile the `--ignore` patterns
+fn ignore_patterns(patterns: &[String]) -> Result<Gitignore> {
+    let mut builder = GitignoreBuilder::new(std::env::current_dir()?);
+    for pattern in patterns {
+        builder.add_line(None, pattern)?;
+    }
+    Ok(builder.build()?)
+}
+
+fn not_ignored(path: &Path, ignored: &Ignore, patterns: &Gitignore, ext: &[String]) -> bool {
     let path = path.to_owned();
-    !ignored.check(&path) && !dirs.contains(&path) && !hashes.contains_key(&path)
+    !ignored.check(&path)
+        && !patterns
+            .matched_path_or_any_parents(&path, path.is_dir())
+            .is_ignore()
+        && (ext.is_empty()
+            || path.extension().is_some_and(|x| {
+                ext.iter()
+                    .any(|e| x.to_string_lossy() == e.trim_start_matches('.'))
+            }))
 }
 
 fn hash(path: &Path) -> String {
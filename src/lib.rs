@@ -45,6 +45,7 @@ let results = shell.run(&[Command {
 assert_eq!(
     results[0].stdout,
     Pipe::String(Some(String::from("\
+benches
 Cargo.lock
 Cargo.toml
 CHANGELOG.md
@@ -66,21 +67,46 @@ use sprint::*;
 
 let shell = Shell {
     shell: Some(String::from("sh -c")),
+    shell_argv: None,
 
     dry_run: false,
     sync: true,
+    keep_going: false,
+    process_group: false,
+    env_clear: false,
+    env_keep: vec![],
     print: true,
+    decorate: true,
+    verbose: false,
+    decorations_to_stderr: false,
+    out: None,
+    fence_empty: true,
+    progress: false,
+    hyperlinks: false,
     color: ColorOverride::Auto,
+    batch_timeout: None,
+    expand_env: false,
+    expand_env_strict: false,
+    normalize_newlines: false,
 
     fence: String::from("```"),
     info: String::from("text"),
     prompt: String::from("$ "),
+    wrap_width: None,
+    wrap_operators: false,
+    output_prefix: None,
+    output_files: None,
 
     fence_style: style("#555555").expect("style"),
     info_style: style("#555555").expect("style"),
     prompt_style: style("#555555").expect("style"),
     command_style: style("#00ffff+bold").expect("style"),
     error_style: style("#ff0000+bold+italic").expect("style"),
+    command_gradient: vec![],
+
+    before: None,
+    after: None,
+    renderer: None,
 };
 
 shell.run(&[Command::new("ls"), Command::new("ls -l")]);
@@ -106,21 +132,35 @@ shell.run(&[Command::new("ls"), Command::new("ls -l")]);
 //--------------------------------------------------------------------------------------------------
 
 use {
-    anstream::{print, println},
     anyhow::{anyhow, Result},
     clap::ValueEnum,
-    owo_colors::{OwoColorize, Rgb, Style},
-    rayon::prelude::*,
-    std::io::{Read, Write},
+    owo_colors::{Effect, OwoColorize, Rgb, Style},
+    std::{
+        borrow::{Borrow, Cow},
+        collections::{BTreeMap, BTreeSet},
+        io::{IsTerminal, Read, Write},
+        sync::{Arc, Mutex},
+        time::{Duration, Instant},
+    },
 };
 
+#[cfg(all(feature = "pty", not(unix)))]
+compile_error!("The `pty` feature is only supported on Unix!");
+
 //--------------------------------------------------------------------------------------------------
 
+/// Where a command's stdin, stdout, or stderr is connected
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Pipe {
+    /// Discard output; stdin reads EOF immediately
     Null,
+    /// Share the parent's stream, e.g. for interactive commands
+    Inherit,
+    /// Share the parent's stdout
     Stdout,
+    /// Share the parent's stderr
     Stderr,
+    /// Write the string to stdin or capture output as a string
     String(Option<String>),
 }
 
@@ -128,18 +168,323 @@ impl Pipe {
     pub fn string() -> Pipe {
         Pipe::String(None)
     }
+
+    /// True if the child shares the parent's stream
+    pub fn is_inherit(&self) -> bool {
+        matches!(self, Pipe::Inherit | Pipe::Stdout | Pipe::Stderr)
+    }
+
+    /// Get the [`std::process::Stdio`] for the pipe
+    fn stdio(&self) -> std::process::Stdio {
+        match self {
+            Pipe::Null => std::process::Stdio::null(),
+            Pipe::Inherit | Pipe::Stdout | Pipe::Stderr => std::process::Stdio::inherit(),
+            Pipe::String(_) => std::process::Stdio::piped(),
+        }
+    }
+
+    /// Get the captured string; empty if nothing was captured
+    pub fn into_string(self) -> String {
+        if let Pipe::String(Some(s)) = self {
+            s
+        } else {
+            String::new()
+        }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+
+/**
+Quote a string for safe use as a single argument in a shell command
+
+Nul bytes cannot be passed to a process and are removed.
+
+```
+use sprint::*;
+
+let pattern = "a $pattern";
+
+assert_eq!(format!("grep {} file", quote(pattern)), "grep 'a $pattern' file");
+```
+*/
+pub fn quote(s: &str) -> String {
+    shlex::try_quote(&s.replace('\0', ""))
+        .expect("quote")
+        .into_owned()
+}
+
+/**
+True if a command uses shell features such as pipes, redirects, `&&`, `;`, variables, command
+substitution, or globs outside of quotes
+
+These only work when the command is run via a shell; see [`Shell::shell`].
+
+```
+use sprint::*;
+
+assert!(uses_shell_features("echo a | grep a"));
+assert!(uses_shell_features("ls *.rs"));
+assert!(uses_shell_features("echo \"$HOME\""));
+assert!(!uses_shell_features("grep 'a|b' file"));
+assert!(!uses_shell_features("echo '$HOME'"));
+```
+*/
+pub fn uses_shell_features(command: &str) -> bool {
+    let mut single = false;
+    let mut double = false;
+    let mut chars = command.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' if !double => single = !single,
+            _ if single => {}
+            '\\' => {
+                chars.next();
+            }
+            '"' => double = !double,
+            '$' | '`' => return true,
+            _ if double => {}
+            '|' | '&' | ';' | '<' | '>' | '*' | '?' | '[' | '(' | ')' => return true,
+            _ => {}
+        }
+    }
+    false
+}
+
+/**
+Expand `$VAR` and `${VAR}` environment variables in a command outside of single quotes
+
+When [`Shell::expand_env`] is set and the shell is disabled, commands are expanded the same way
+but within each word after splitting, so variable values are never parsed as shell syntax.
+Unknown variables expand to an empty string, or are an error if `strict` is set.
+A `$` not followed by a variable name and an escaped `\$` are left as is.
+
+```
+use sprint::*;
+
+let path = std::env::var("PATH").unwrap();
+
+assert_eq!(
+    expand_env("echo \"in $PATH\" '$PATH' ${PATH}s \\$5", false).unwrap(),
+    format!("echo \"in {path}\" '$PATH' {path}s \\$5"),
+);
+assert_eq!(expand_env("echo $SPRINT_EXPAND_UNSET", false).unwrap(), "echo ");
+assert!(expand_env("echo $SPRINT_EXPAND_UNSET", true).is_err());
+```
+*/
+pub fn expand_env(command: &str, strict: bool) -> Result<String> {
+    expand_vars(command, |name| std::env::var(name).ok(), strict)
+}
+
+/// Expand variables via a lookup function; see [`expand_env`]
+fn expand_vars(
+    command: &str,
+    lookup: impl Fn(&str) -> Option<String>,
+    strict: bool,
+) -> Result<String> {
+    let mut r = String::new();
+    let mut single = false;
+    let mut double = false;
+    let mut chars = command.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' if !double => single = !single,
+            '"' if !single => double = !double,
+            '\\' if !single => {
+                r.push(c);
+                if let Some(c) = chars.next() {
+                    r.push(c);
+                }
+                continue;
+            }
+            '$' if !single => {
+                match expand_var(&mut chars, &lookup, strict, command)? {
+                    Some(value) => r.push_str(&value),
+                    None => r.push(c),
+                }
+                continue;
+            }
+            _ => {}
+        }
+        r.push(c);
+    }
+    Ok(r)
+}
+
+/// Expand the variable reference following a `$`; returns `None` if there isn't one
+fn expand_var(
+    chars: &mut std::iter::Peekable<std::str::Chars>,
+    lookup: impl Fn(&str) -> Option<String>,
+    strict: bool,
+    command: &str,
+) -> Result<Option<String>> {
+    let braced = chars.next_if_eq(&'{').is_some();
+    let mut name = String::new();
+    while let Some(c) = chars
+        .next_if(|c| c.is_ascii_alphabetic() || *c == '_' || !name.is_empty() && c.is_ascii_digit())
+    {
+        name.push(c);
+    }
+    if braced && (name.is_empty() || chars.next_if_eq(&'}').is_none()) {
+        return Err(anyhow!(
+            "Invalid variable reference in command: {command:?}!"
+        ));
+    }
+    if name.is_empty() {
+        return Ok(None);
+    }
+    match lookup(&name) {
+        Some(value) => Ok(Some(value)),
+        None if strict => Err(anyhow!("Environment variable `{name}` is not set!")),
+        None => Ok(Some(String::new())),
+    }
+}
+
+/**
+Split a command into words like [`shlex::split`] while expanding variables within each word
+
+Expanded values are never parsed as syntax: quoted values stay in one word and unquoted values
+are only split on whitespace.
+*/
+fn split_vars(
+    command: &str,
+    lookup: impl Fn(&str) -> Option<String>,
+    strict: bool,
+) -> Result<Vec<String>> {
+    let invalid = || anyhow!("Failed to parse command: {command:?}!");
+    let mut words = vec![];
+    let mut word: Option<String> = None;
+    let mut chars = command.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            ' ' | '\t' | '\n' => words.extend(word.take()),
+            '#' if word.is_none() => while chars.next_if(|c| *c != '\n').is_some() {},
+            '\\' => match chars.next() {
+                Some('\n') => {}
+                Some(c) => word.get_or_insert_default().push(c),
+                None => return Err(invalid()),
+            },
+            '\'' => {
+                let word = word.get_or_insert_default();
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => return Err(invalid()),
+                    }
+                }
+            }
+            '"' => {
+                let word = word.get_or_insert_default();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('$' | '`' | '"' | '\\')) => word.push(c),
+                            Some('\n') => {}
+                            Some(c) => {
+                                word.push('\\');
+                                word.push(c);
+                            }
+                            None => return Err(invalid()),
+                        },
+                        Some('$') => match expand_var(&mut chars, &lookup, strict, command)? {
+                            Some(value) => word.push_str(&value),
+                            None => word.push('$'),
+                        },
+                        Some(c) => word.push(c),
+                        None => return Err(invalid()),
+                    }
+                }
+            }
+            '$' => match expand_var(&mut chars, &lookup, strict, command)? {
+                Some(value) => {
+                    for c in value.chars() {
+                        if c.is_whitespace() {
+                            words.extend(word.take());
+                        } else {
+                            word.get_or_insert_default().push(c);
+                        }
+                    }
+                }
+                None => word.get_or_insert_default().push(c),
+            },
+            _ => word.get_or_insert_default().push(c),
+        }
+    }
+    words.extend(word);
+    Ok(words)
 }
 
 //--------------------------------------------------------------------------------------------------
 
-/// Create a [`Style`] from a [`&str`] specification
-pub fn style(s: &str) -> Result<Style> {
+/**
+Error parsing a [`&str`] style specification; see [`style`]
+
+Converts to [`anyhow::Error`] so `?` works in functions returning [`anyhow::Result`].
+
+```
+use sprint::*;
+
+assert_eq!(style("bold+nope"), Err(StyleError::InvalidToken(String::from("nope"))));
+assert_eq!(style("#12345g"), Err(StyleError::BadHex(String::from("12345g"))));
+assert_eq!(style("on-#fff"), Err(StyleError::WrongLength(String::from("fff"))));
+assert_eq!(style("gray:256"), Err(StyleError::OutOfRange(String::from("256"))));
+```
+*/
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum StyleError {
+    /// Unknown color or effect
+    InvalidToken(String),
+    /// RGB color with a non-hexadecimal digit
+    BadHex(String),
+    /// RGB color without exactly 6 digits
+    WrongLength(String),
+    /// Gray level above 255
+    OutOfRange(String),
+}
+
+impl std::fmt::Display for StyleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            StyleError::InvalidToken(token) => write!(f, "Invalid style spec token: {token:?}!"),
+            StyleError::BadHex(color) => {
+                write!(f, "Invalid style spec color: {color:?} is not hexadecimal!")
+            }
+            StyleError::WrongLength(color) => {
+                write!(f, "Invalid style spec color: {color:?} is not 6 digits!")
+            }
+            StyleError::OutOfRange(level) => {
+                write!(f, "Invalid style spec gray level: {level:?} is not 0-255!")
+            }
+        }
+    }
+}
+
+impl std::error::Error for StyleError {}
+
+/// Create a [`Style`] from a [`&str`] specification; an empty specification is a plain style
+///
+/// Effects may be abbreviated: `b` (bold), `i` (italic), `dim` (dimmed), `ul` or `under`
+/// (underline), `strike` (strikethrough), and `blink-fast` (blink_fast).
+///
+/// `gray:N` or `grey:N` (and `on-gray:N` or `on-grey:N`) is an RGB gray with each channel set to
+/// `N` (0-255), e.g. `gray:85` is `#555555`.
+pub fn style(s: &str) -> Result<Style, StyleError> {
     let mut r = Style::new();
+    if s.is_empty() {
+        return Ok(r);
+    }
     for i in s.split('+') {
         if let Some(color) = i.strip_prefix('#') {
             r = r.color(html(color)?);
         } else if let Some(color) = i.strip_prefix("on-#") {
             r = r.on_color(html(color)?);
+        } else if let Some(level) = i.strip_prefix("gray:").or(i.strip_prefix("grey:")) {
+            r = r.color(gray(i, level)?);
+        } else if let Some(level) = i.strip_prefix("on-gray:").or(i.strip_prefix("on-grey:")) {
+            r = r.on_color(gray(i, level)?);
         } else {
             match i {
                 "black" => r = r.black(),
@@ -152,15 +497,15 @@ pub fn style(s: &str) -> Result<Style> {
                 "cyan" => r = r.cyan(),
                 "white" => r = r.white(),
                 //---
-                "bold" => r = r.bold(),
-                "italic" => r = r.italic(),
-                "dimmed" => r = r.dimmed(),
-                "underline" => r = r.underline(),
+                "bold" | "b" => r = r.bold(),
+                "italic" | "i" => r = r.italic(),
+                "dimmed" | "dim" => r = r.dimmed(),
+                "underline" | "under" | "ul" => r = r.underline(),
                 "blink" => r = r.blink(),
-                "blink_fast" => r = r.blink_fast(),
+                "blink_fast" | "blink-fast" => r = r.blink_fast(),
                 "reversed" => r = r.reversed(),
                 "hidden" => r = r.hidden(),
-                "strikethrough" => r = r.strikethrough(),
+                "strikethrough" | "strike" => r = r.strikethrough(),
                 //---
                 "bright-black" => r = r.bright_black(),
                 "bright-red" => r = r.bright_red(),
@@ -192,21 +537,118 @@ pub fn style(s: &str) -> Result<Style> {
                 "on-bright-cyan" => r = r.on_bright_cyan(),
                 "on-bright-white" => r = r.on_bright_white(),
                 //---
-                _ => return Err(anyhow!("Invalid style spec: {s:?}!")),
+                _ => return Err(StyleError::InvalidToken(i.to_string())),
             }
         }
     }
     Ok(r)
 }
 
-fn html(rrggbb: &str) -> Result<Rgb> {
-    let r = u8::from_str_radix(&rrggbb[0..2], 16)?;
-    let g = u8::from_str_radix(&rrggbb[2..4], 16)?;
-    let b = u8::from_str_radix(&rrggbb[4..6], 16)?;
-    Ok(Rgb(r, g, b))
+const COLORS: [&str; 8] = [
+    "black", "red", "green", "yellow", "blue", "magenta", "cyan", "white",
+];
+
+const EFFECTS: [(&str, Effect); 9] = [
+    ("bold", Effect::Bold),
+    ("italic", Effect::Italic),
+    ("dimmed", Effect::Dimmed),
+    ("underline", Effect::Underline),
+    ("blink", Effect::Blink),
+    ("blink_fast", Effect::BlinkFast),
+    ("reversed", Effect::Reversed),
+    ("hidden", Effect::Hidden),
+    ("strikethrough", Effect::Strikethrough),
+];
+
+/**
+Create a [`&str`] specification from a [`Style`]; the inverse of [`style`]
+
+The specification lists the foreground color, background color, and effects, in that order.
+RGB colors are written as `#rrggbb`; `purple` is an alias of `magenta` and is written as the latter.
+
+```
+use sprint::*;
+
+let s = style("#ff0000+on-bright-blue+bold+underline").unwrap();
+
+assert_eq!(style_to_string(&s), "#ff0000+on-bright-blue+bold+underline");
+assert_eq!(style(&style_to_string(&s)).unwrap(), s);
+```
+*/
+pub fn style_to_string(style: &Style) -> String {
+    let mut r = vec![];
+
+    // Render the foreground and background separately so their ANSI codes cannot run together
+    let fg = Prefix {
+        style: style.remove_bg().remove_all_effects(),
+    }
+    .to_string();
+    let bg = Prefix {
+        style: style.remove_fg().remove_all_effects(),
+    }
+    .to_string();
+    if let Some(color) = color_spec(&fg, "3", "9", "38") {
+        r.push(color);
+    }
+    if let Some(color) = color_spec(&bg, "4", "10", "48") {
+        r.push(format!("on-{color}"));
+    }
+
+    // Applying an effect that is already set leaves the style unchanged
+    for (name, effect) in EFFECTS {
+        if style.effect(effect) == *style {
+            r.push(name.to_string());
+        }
+    }
+
+    r.join("+")
+}
+
+fn color_spec(prefix: &str, normal: &str, bright: &str, rgb: &str) -> Option<String> {
+    let codes = prefix.strip_prefix("\x1b[")?.strip_suffix('m')?;
+    if let Some(rgb) = codes.strip_prefix(rgb).and_then(|x| x.strip_prefix(";2;")) {
+        let c = rgb
+            .split(';')
+            .map(|x| x.parse::<u8>())
+            .collect::<Result<Vec<_>, _>>()
+            .ok()?;
+        Some(format!(
+            "#{:02x}{:02x}{:02x}",
+            c.first()?,
+            c.get(1)?,
+            c.get(2)?
+        ))
+    } else if let Some(i) = codes.strip_prefix(bright) {
+        Some(format!("bright-{}", COLORS.get(i.parse::<usize>().ok()?)?))
+    } else {
+        let i = codes.strip_prefix(normal)?;
+        COLORS.get(i.parse::<usize>().ok()?).map(|x| x.to_string())
+    }
+}
+
+/// Parse the level of a `gray:N` style token
+fn gray(token: &str, level: &str) -> Result<Rgb, StyleError> {
+    if level.is_empty() || !level.chars().all(|c| c.is_ascii_digit()) {
+        return Err(StyleError::InvalidToken(token.to_string()));
+    }
+    let n = level
+        .parse::<u8>()
+        .map_err(|_e| StyleError::OutOfRange(level.to_string()))?;
+    Ok(Rgb(n, n, n))
+}
+
+fn html(rrggbb: &str) -> Result<Rgb, StyleError> {
+    if !rrggbb.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(StyleError::BadHex(rrggbb.to_string()));
+    }
+    if rrggbb.len() != 6 {
+        return Err(StyleError::WrongLength(rrggbb.to_string()));
+    }
+    let channel = |i: usize| u8::from_str_radix(&rrggbb[i..i + 2], 16).expect("hex");
+    Ok(Rgb(channel(0), channel(2), channel(4)))
 }
 
-#[derive(Clone, Debug, Default, ValueEnum)]
+#[derive(Clone, Debug, Default, PartialEq, Eq, ValueEnum)]
 pub enum ColorOverride {
     #[default]
     Auto,
@@ -215,6 +657,9 @@ pub enum ColorOverride {
 }
 
 impl ColorOverride {
+    /// Set the global color choice; see also [`Shell::color`] to set it for a single [`Shell`]
+    ///
+    /// `Auto` leaves the global choice alone so colors are detected per stream.
     pub fn init(&self) {
         match self {
             ColorOverride::Always => anstream::ColorChoice::Always.write_global(),
@@ -222,6 +667,55 @@ impl ColorOverride {
             ColorOverride::Auto => {}
         }
     }
+
+    /// Get the corresponding [`anstream::ColorChoice`]
+    pub fn choice(&self) -> anstream::ColorChoice {
+        match self {
+            ColorOverride::Auto => anstream::ColorChoice::Auto,
+            ColorOverride::Always => anstream::ColorChoice::Always,
+            ColorOverride::Never => anstream::ColorChoice::Never,
+        }
+    }
+}
+
+impl std::str::FromStr for ColorOverride {
+    type Err = anyhow::Error;
+
+    /**
+    Parse `auto`, `always`, or `never` case-insensitively
+
+    ```
+    use sprint::*;
+
+    assert_eq!("Always".parse::<ColorOverride>().unwrap(), ColorOverride::Always);
+    assert!("sometimes".parse::<ColorOverride>().is_err());
+    ```
+    */
+    fn from_str(s: &str) -> Result<ColorOverride> {
+        match s.to_lowercase().as_str() {
+            "auto" => Ok(ColorOverride::Auto),
+            "always" => Ok(ColorOverride::Always),
+            "never" => Ok(ColorOverride::Never),
+            _ => Err(anyhow!(
+                "Invalid color: {s:?}! Expected `auto`, `always`, or `never`."
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for ColorOverride {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            ColorOverride::Auto => "auto",
+            ColorOverride::Always => "always",
+            ColorOverride::Never => "never",
+        })
+    }
+}
+
+/// True if stdout is a terminal
+pub fn is_terminal() -> bool {
+    std::io::stdout().is_terminal()
 }
 
 //--------------------------------------------------------------------------------------------------
@@ -236,10 +730,6 @@ impl std::fmt::Display for Prefix {
     }
 }
 
-fn print_prefix(style: Style) {
-    print!("{}", Prefix { style });
-}
-
 //--------------------------------------------------------------------------------------------------
 
 struct Suffix {
@@ -252,354 +742,4131 @@ impl std::fmt::Display for Suffix {
     }
 }
 
-fn print_suffix(style: Style) {
-    print!("{}", Suffix { style });
+/// Get the length of an iterator if it is known exactly
+fn exact_len(iter: &impl Iterator) -> Option<usize> {
+    match iter.size_hint() {
+        (lower, Some(upper)) if lower == upper => Some(lower),
+        _ => None,
+    }
 }
 
-//--------------------------------------------------------------------------------------------------
-
-/**
-Command runner
-
-*Please see also the module-level documentation for a high-level description and examples.*
-
-```
-use sprint::*;
+/// Indent for continuation lines of a wrapped command
+const WRAP_INDENT: &str = "    ";
 
-// Use the default configuration:
+/// Wrap a command at its operators, and pipes if `pipes` is set, for printing; skips quoted and
+/// escaped operators, e.g. in an `awk` program
+fn wrap(command: &str, pipes: bool) -> String {
+    const OPERATORS: [(&str, &str); 4] = [
+        (" && ", " \\\n&& "),
+        (" || ", " \\\n|| "),
+        (" | ", " \\\n| "),
+        ("; ", "; \\\n"),
+    ];
 
-let shell = Shell::default();
+    let mut r = String::new();
+    let mut quote: Option<char> = None;
+    let mut escaped = false;
+    let mut rest = command;
 
-// Or a custom configuration:
+    while let Some(c) = rest.chars().next() {
+        if quote.is_none() && !escaped {
+            if let Some((from, to)) = OPERATORS
+                .iter()
+                .filter(|(from, _to)| pipes || *from != " | ")
+                .find(|(from, _to)| rest.starts_with(from))
+            {
+                r.push_str(to);
+                rest = &rest[from.len()..];
+                continue;
+            }
+        }
 
-let shell = Shell {
-    shell: Some(String::from("sh -c")),
-    //shell: Some(String::from("bash -c")), // Use bash
-    //shell: Some(String::from("bash -xeo pipefail -c")), // Use bash w/ options
-    //shell: None, // Run directly instead of a shell
+        if escaped {
+            escaped = false;
+        } else if c == '\\' && quote != Some('\'') {
+            escaped = true;
+        } else if quote == Some(c) {
+            quote = None;
+        } else if quote.is_none() && matches!(c, '\'' | '"') {
+            quote = Some(c);
+        }
 
-    dry_run: false,
-    sync: true,
-    print: true,
-    color: ColorOverride::default(),
+        r.push(c);
+        rest = &rest[c.len_utf8()..];
+    }
 
-    fence: String::from("```"),
-    info: String::from("text"),
-    prompt: String::from("$ "),
+    r
+}
 
-    fence_style: style("#555555").expect("style"),
-    info_style: style("#555555").expect("style"),
-    prompt_style: style("#555555").expect("style"),
-    command_style: style("#00ffff+bold").expect("style"),
-    error_style: style("#ff0000+bold+italic").expect("style"),
-};
+/// Style a string unless it is empty, so an empty string doesn't print stray escape codes
+fn styled(s: &str, style: Style) -> String {
+    if s.is_empty() {
+        String::new()
+    } else {
+        s.style(style).to_string()
+    }
+}
 
-// Or modify it on the fly:
+/**
+Style each non-whitespace character of a string with the next of the given styles in turn; uses
+the fallback style for the whole string if there are no styles
 
-let mut shell = Shell::default();
+```
+use sprint::*;
 
-shell.shell = None;
-shell.sync = false;
+let styles = [style("red").unwrap(), style("blue").unwrap()];
 
-// ...
+assert_eq!(
+    gradient("ab c", style("").unwrap(), &styles),
+    "\x1b[31ma\x1b[0m\x1b[34mb\x1b[0m \x1b[31mc\x1b[0m",
+);
+assert_eq!(gradient("ab c", style("bold").unwrap(), &[]), "\x1b[1mab c\x1b[0m");
 ```
 */
-#[derive(Clone, Debug)]
-pub struct Shell {
-    pub shell: Option<String>,
+pub fn gradient(s: &str, fallback: Style, styles: &[Style]) -> String {
+    if styles.is_empty() {
+        return s.style(fallback).to_string();
+    }
 
-    pub dry_run: bool,
-    pub sync: bool,
-    pub print: bool,
-    pub color: ColorOverride,
+    let mut styles = styles.iter().cycle();
+    s.chars()
+        .map(|c| {
+            if c.is_whitespace() {
+                c.to_string()
+            } else {
+                c.style(*styles.next().unwrap()).to_string()
+            }
+        })
+        .collect()
+}
 
-    pub fence: String,
-    pub info: String,
-    pub prompt: String,
+/// True if a path is an executable file; see [`Shell::which`]
+fn is_executable(path: &std::path::Path) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        path.metadata()
+            .is_ok_and(|x| x.is_file() && x.permissions().mode() & 0o111 != 0)
+    }
 
-    pub fence_style: Style,
-    pub info_style: Style,
-    pub prompt_style: Style,
-    pub command_style: Style,
-    pub error_style: Style,
+    #[cfg(not(unix))]
+    path.is_file()
 }
 
-impl Default for Shell {
-    /// Default [`Shell`]
-    fn default() -> Shell {
-        Shell {
-            shell: Some(String::from("sh -c")),
+/// Set the niceness and umask of the child process; see [`Command::nice`] and [`Command::umask`]
+#[cfg(unix)]
+fn pre_exec(
+    command: &Command,
+) -> Option<impl FnMut() -> std::io::Result<()> + Send + Sync + 'static> {
+    let (nice, umask) = (command.nice, command.umask);
+    if nice.is_none() && umask.is_none() {
+        return None;
+    }
 
-            dry_run: false,
-            sync: true,
-            print: true,
-            color: ColorOverride::default(),
+    Some(move || {
+        if let Some(nice) = nice {
+            if unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, nice) } == -1 {
+                return Err(std::io::Error::last_os_error());
+            }
+        }
+        if let Some(umask) = umask {
+            unsafe {
+                libc::umask(umask as libc::mode_t);
+            }
+        }
+        Ok(())
+    })
+}
 
-            fence: String::from("```"),
-            info: String::from("text"),
-            prompt: String::from("$ "),
+/// Take an owned command, leaving a default one, or clone a borrowed command
+fn take_command(command: &mut Cow<Command>) -> Command {
+    match command {
+        Cow::Borrowed(command) => command.clone(),
+        Cow::Owned(command) => std::mem::take(command),
+    }
+}
 
-            fence_style: style("#555555").expect("style"),
-            info_style: style("#555555").expect("style"),
-            prompt_style: style("#555555").expect("style"),
-            command_style: style("#00ffff+bold").expect("style"),
-            error_style: style("#ff0000+bold+italic").expect("style"),
+/// Result of a command not run due to [`Shell::dry_run`]
+fn dry_run(mut r: Command) -> Command {
+    r.dry_run = true;
+    r
+}
+
+/// Result of a command with nothing to run; skipped with success
+fn skipped(mut r: Command) -> Command {
+    for pipe in [&mut r.stdout, &mut r.stderr] {
+        if matches!(pipe, Pipe::String(_)) {
+            *pipe = Pipe::String(Some(String::new()));
         }
     }
+    r.code = Some(0);
+    r
 }
 
-impl Shell {
-    /// Run command(s)
-    pub fn run(&self, commands: &[Command]) -> Vec<Command> {
-        if self.sync {
-            if self.print {
-                self.print_fence(0);
-                println!("{}", self.info.style(self.info_style));
-            }
+//--------------------------------------------------------------------------------------------------
 
-            let mut r = vec![];
-            let mut error = None;
+/**
+Callback invoked with a [`Command`]; see [`Shell::before`] and [`Shell::after`]
 
-            for (i, command) in commands.iter().enumerate() {
-                if i > 0 && self.print && !self.dry_run {
-                    println!();
-                }
+```
+use {sprint::*, std::sync::{Arc, Mutex}};
 
-                let result = self.run1(command);
+let log = Arc::new(Mutex::new(vec![]));
+let log2 = log.clone();
 
-                if let Some(code) = &result.code {
-                    if !result.codes.contains(code) {
-                        error = Some(format!(
-                            "**Command `{}` exited with code: `{code}`!**",
-                            result.command,
-                        ));
-                    }
-                } else if !self.dry_run {
-                    error = Some(format!(
-                        "**Command `{}` was killed by a signal!**",
-                        result.command,
+let shell = Shell {
+    print: false,
+    after: Some(Hook::new(move |result| {
+        log2.lock().unwrap().push(result.code);
+    })),
+    ..Default::default()
+};
+
+shell.run_str(&["true", "false"]);
+
+assert_eq!(*log.lock().unwrap(), vec![Some(0), Some(1)]);
+```
+*/
+#[derive(Clone)]
+pub struct Hook(pub Arc<dyn Fn(&Command) + Send + Sync>);
+
+impl Hook {
+    pub fn new(f: impl Fn(&Command) + Send + Sync + 'static) -> Hook {
+        Hook(Arc::new(f))
+    }
+}
+
+impl std::fmt::Debug for Hook {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("Hook")
+    }
+}
+
+impl PartialEq for Hook {
+    /// Hooks are equal if they are the same callback
+    fn eq(&self, other: &Hook) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+
+/**
+Custom writer for a [`Shell`]'s output; see [`Shell::out`]
+
+```
+use {sprint::*, std::sync::{Arc, Mutex}};
+
+let buffer = Arc::new(Mutex::new(vec![]));
+
+let shell = Shell {
+    out: Some(Writer(buffer.clone())),
+    ..Default::default()
+};
+
+shell.run_str(&["echo hello"]);
+
+assert_eq!(
+    String::from_utf8(buffer.lock().unwrap().clone()).unwrap(),
+    "```text\n$ echo hello\nhello\n```\n\n",
+);
+```
+*/
+#[derive(Clone)]
+pub struct Writer(pub Arc<Mutex<dyn Write + Send>>);
+
+impl Writer {
+    pub fn new(w: impl Write + Send + 'static) -> Writer {
+        Writer(Arc::new(Mutex::new(w)))
+    }
+}
+
+impl std::fmt::Debug for Writer {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("Writer")
+    }
+}
+
+impl PartialEq for Writer {
+    /// Writers are equal if they are the same writer
+    fn eq(&self, other: &Writer) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+/// Writer that copies everything written to a buffer; see [`Shell::render`]
+struct Tee {
+    buffer: Arc<Mutex<Vec<u8>>>,
+    out: Option<Writer>,
+    stderr: bool,
+}
+
+impl Write for Tee {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.buffer.lock().expect("lock").extend_from_slice(buf);
+        if let Some(out) = &self.out {
+            out.0.lock().expect("lock").write_all(buf)?;
+        } else if self.stderr {
+            std::io::stderr().write_all(buf)?;
+        } else {
+            std::io::stdout().write_all(buf)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        if let Some(out) = &self.out {
+            out.0.lock().expect("lock").flush()
+        } else if self.stderr {
+            std::io::stderr().flush()
+        } else {
+            std::io::stdout().flush()
+        }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+
+/**
+Renders the decorations a [`Shell`] prints around commands; see [`Shell::renderer`]
+
+[`MarkdownRenderer`] is the default.
+Implement this trait to print something other than Markdown fenced code blocks.
+
+```
+use {sprint::*, std::sync::Arc};
+
+struct PlainRenderer;
+
+impl Renderer for PlainRenderer {
+    fn open(&self, _shell: &Shell, _info: &str) -> String {
+        String::new()
+    }
+
+    fn close(&self, _shell: &Shell) -> String {
+        String::new()
+    }
+
+    fn command(&self, _shell: &Shell, command: &Command) -> String {
+        format!("+ {}\n", command.command)
+    }
+
+    fn error(&self, _shell: &Shell, error: &str) -> String {
+        format!("error: {error}\n")
+    }
+}
+
+let shell = Shell {
+    renderer: Some(Arc::new(PlainRenderer)),
+    color: ColorOverride::Never,
+    ..Default::default()
+};
+
+assert_eq!(
+    shell.render(&[Command::new("echo hello"), Command::new("exit 2")]),
+    "+ echo hello\nhello\n\n+ exit 2\nerror: Command `exit 2` exited with code: `2`!\n",
+);
+```
+*/
+pub trait Renderer: Send + Sync {
+    /// Start a block of commands with the given info
+    fn open(&self, shell: &Shell, info: &str) -> String;
+
+    /// End a block of commands
+    fn close(&self, shell: &Shell) -> String;
+
+    /// Prompt and command before it runs
+    fn command(&self, shell: &Shell, command: &Command) -> String;
+
+    /// Error message for a failed command or batch
+    fn error(&self, shell: &Shell, error: &str) -> String;
+
+    /// Captured output of a command; unchanged by default
+    fn output(&self, _shell: &Shell, output: &str) -> String {
+        output.to_string()
+    }
+
+    /// Separator between commands in the same block; a blank line by default
+    fn separator(&self, _shell: &Shell) -> String {
+        String::from("\n")
+    }
+
+    /// Header before a section of commands; a Markdown heading in the info style by default; see
+    /// [`Shell::run_sections`]
+    fn section(&self, shell: &Shell, title: &str) -> String {
+        format!("{}\n\n", format!("## {title}").style(shell.info_style))
+    }
+}
+
+impl std::fmt::Debug for dyn Renderer {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("Renderer")
+    }
+}
+
+impl PartialEq for dyn Renderer {
+    /// Renderers are equal if they are the same renderer
+    fn eq(&self, other: &dyn Renderer) -> bool {
+        std::ptr::addr_eq(self, other)
+    }
+}
+
+/// Default [`Renderer`]: Markdown fenced code blocks styled per the [`Shell`]
+///
+/// An empty [`Shell::fence`] skips the fence lines (including the info), and an empty
+/// [`Shell::info`] or [`Shell::prompt`] is skipped instead of printed as an empty styled string.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct MarkdownRenderer;
+
+impl Renderer for MarkdownRenderer {
+    fn open(&self, shell: &Shell, info: &str) -> String {
+        if shell.fence.is_empty() {
+            return String::new();
+        }
+        format!(
+            "{}{}\n",
+            shell.fence.style(shell.fence_style),
+            styled(info, shell.info_style),
+        )
+    }
+
+    fn close(&self, shell: &Shell) -> String {
+        if shell.fence.is_empty() {
+            return String::from("\n");
+        }
+        format!("{}\n\n", shell.fence.style(shell.fence_style))
+    }
+
+    fn command(&self, shell: &Shell, command: &Command) -> String {
+        format!(
+            "{}{}\n",
+            if shell.dry_run {
+                String::new()
+            } else {
+                styled(&shell.prompt, shell.prompt_style)
+            },
+            gradient(
+                &shell.wrap_command(&command.command, !shell.dry_run),
+                shell.command_style,
+                &shell.command_gradient,
+            ),
+        )
+    }
+
+    fn error(&self, shell: &Shell, error: &str) -> String {
+        format!("{}\n\n", format!("**{error}**").style(shell.error_style))
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+
+/**
+Command runner
+
+*Please see also the module-level documentation for a high-level description and examples.*
+
+```
+use sprint::*;
+
+// Use the default configuration:
+
+let shell = Shell::default();
+
+// Or a custom configuration:
+
+let shell = Shell {
+    shell: Some(String::from("sh -c")),
+    //shell: Some(String::from("bash -c")), // Use bash
+    //shell: Some(String::from("bash -xeo pipefail -c")), // Use bash w/ options
+    //shell: None, // Run directly instead of a shell
+    shell_argv: None,
+    //shell_argv: Some(vec![String::from("/path with spaces/sh"), String::from("-c")]),
+
+    dry_run: false,
+    sync: true,
+    keep_going: false,
+    process_group: false,
+    env_clear: false,
+    env_keep: vec![],
+    //env_keep: vec![String::from("PATH"), String::from("HOME")],
+    print: true,
+    decorate: true,
+    verbose: false,
+    decorations_to_stderr: false,
+    out: None,
+    fence_empty: true,
+    progress: false,
+    hyperlinks: false,
+    color: ColorOverride::default(),
+    batch_timeout: None,
+    expand_env: false,
+    expand_env_strict: false,
+    normalize_newlines: false,
+
+    fence: String::from("```"),
+    info: String::from("text"),
+    prompt: String::from("$ "),
+    wrap_width: None,
+    wrap_operators: false,
+    output_prefix: None,
+    output_files: None,
+
+    fence_style: style("#555555").expect("style"),
+    info_style: style("#555555").expect("style"),
+    prompt_style: style("#555555").expect("style"),
+    command_style: style("#00ffff+bold").expect("style"),
+    error_style: style("#ff0000+bold+italic").expect("style"),
+    command_gradient: vec![],
+
+    before: None,
+    after: None,
+    renderer: None,
+};
+
+// Or modify it on the fly:
+
+let mut shell = Shell::default();
+
+shell.shell = None;
+shell.sync = false;
+
+// ...
+```
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub struct Shell {
+    pub shell: Option<String>,
+    /// Pre-split shell program and arguments, e.g. for a shell in a path with spaces; used
+    /// verbatim instead of [`Shell::shell`] if set
+    pub shell_argv: Option<Vec<String>>,
+
+    pub dry_run: bool,
+    pub sync: bool,
+    /// Keep running the remaining commands after a command fails and print a summary
+    pub keep_going: bool,
+    /// Run each command in its own process group so killing it also kills its children
+    pub process_group: bool,
+    /// Run commands with an empty environment except for [`Shell::env_keep`], e.g. for
+    /// reproducible builds
+    pub env_clear: bool,
+    /// Variables passed through from the parent environment when [`Shell::env_clear`] is set,
+    /// e.g. `PATH`, `HOME`, and `TERM`
+    pub env_keep: Vec<String>,
+    pub print: bool,
+    /// Print the fences, info, prompts, and commands along with the output
+    pub decorate: bool,
+    /// Print the program and arguments of each command before running it; see [`Shell::resolve`]
+    pub verbose: bool,
+    /// Print the fences, info, prompts, and commands to stderr to keep stdout clean for piping
+    pub decorations_to_stderr: bool,
+    /// Write the printed output here instead of to the terminal; see [`Writer`]
+    pub out: Option<Writer>,
+    /// Print the fences of a block even if its commands produce no output
+    pub fence_empty: bool,
+    /// Show a progress bar on stderr while running commands; requires the `indicatif` feature
+    pub progress: bool,
+    /// Display file paths as clickable terminal hyperlinks; see [`Shell::hyperlink`]
+    pub hyperlinks: bool,
+    pub color: ColorOverride,
+    /// Time limit for a batch; the running command is killed and the rest skipped once exceeded
+    pub batch_timeout: Option<Duration>,
+    /// Expand environment variables in commands run without a shell; see [`expand_env`]
+    pub expand_env: bool,
+    /// Make unknown variables an error instead of empty when expanding; see [`Shell::expand_env`]
+    pub expand_env_strict: bool,
+    /// Convert `\r\n` to `\n` in captured [`Pipe::String`] output
+    pub normalize_newlines: bool,
+
+    /// Fence around each block of commands; the fence lines are skipped if empty
+    pub fence: String,
+    /// Info after the opening fence; skipped if empty, see also [`Command::info`]
+    pub info: String,
+    /// Prompt before each command; skipped if empty
+    pub prompt: String,
+    /// Wrap printed commands at this width; `0` uses the terminal width
+    pub wrap_width: Option<usize>,
+    /// Also wrap printed commands at pipes (`|`), in addition to `&&`, `||`, and `;`
+    pub wrap_operators: bool,
+    /// Prefix each line of command output that would go to the terminal, e.g. `> `
+    pub output_prefix: Option<String>,
+    /// Path prefix to also write each command's stdout and stderr to, e.g. `logs/sprint` writes
+    /// `logs/sprint-0.out` and `logs/sprint-0.err` for the first command of a batch; output
+    /// that would go to the terminal is printed after the command exits (not supported by
+    /// `Shell::run_async`)
+    pub output_files: Option<std::path::PathBuf>,
+
+    pub fence_style: Style,
+    pub info_style: Style,
+    pub prompt_style: Style,
+    pub command_style: Style,
+    pub error_style: Style,
+    /// Styles to cycle through for each non-whitespace character of a command instead of
+    /// [`Shell::command_style`], e.g. for a gradient; ignored if empty
+    pub command_gradient: Vec<Style>,
+
+    pub before: Option<Hook>,
+    pub after: Option<Hook>,
+
+    /// Renders the fences, commands, and errors; [`MarkdownRenderer`] if `None`
+    pub renderer: Option<Arc<dyn Renderer>>,
+}
+
+impl Default for Shell {
+    /// Default [`Shell`]
+    ///
+    /// The shell is taken from the `SPRINT_SHELL` environment variable if set, otherwise `sh -c`.
+    fn default() -> Shell {
+        Shell {
+            shell: Some(std::env::var("SPRINT_SHELL").unwrap_or_else(|_| String::from("sh -c"))),
+            shell_argv: None,
+
+            dry_run: false,
+            sync: true,
+            keep_going: false,
+            process_group: false,
+            env_clear: false,
+            env_keep: vec![],
+            print: true,
+            decorate: true,
+            verbose: false,
+            decorations_to_stderr: false,
+            out: None,
+            fence_empty: true,
+            progress: false,
+            hyperlinks: false,
+            color: ColorOverride::default(),
+            batch_timeout: None,
+            expand_env: false,
+            expand_env_strict: false,
+            normalize_newlines: false,
+
+            fence: String::from("```"),
+            info: String::from("text"),
+            prompt: String::from("$ "),
+            wrap_width: None,
+            wrap_operators: false,
+            output_prefix: None,
+            output_files: None,
+
+            fence_style: style("#555555").expect("style"),
+            info_style: style("#555555").expect("style"),
+            prompt_style: style("#555555").expect("style"),
+            command_style: style("#00ffff+bold").expect("style"),
+            error_style: style("#ff0000+bold+italic").expect("style"),
+            command_gradient: vec![],
+
+            before: None,
+            after: None,
+            renderer: None,
+        }
+    }
+}
+
+impl Shell {
+    /**
+    Shell that neither runs nor prints anything; the recommended test double
+
+    Sets [`Shell::dry_run`] and clears [`Shell::print`] so the results of [`Shell::run`] are the
+    commands that would have been run, marked with [`Command::dry_run`], which lets code that
+    generates commands be unit tested.
+
+    ```
+    use sprint::*;
+
+    fn build(shell: &Shell, target: &str) -> Vec<Command> {
+        shell.run(&[Command::new(&format!("make {target}"))])
+    }
+
+    let results = build(&Shell::null(), "all");
+
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].command, "make all");
+    assert_eq!(results[0].code, None);
+    assert!(results[0].dry_run && results_ok(&results));
+    ```
+    */
+    pub fn null() -> Shell {
+        Shell {
+            dry_run: true,
+            print: false,
+            ..Default::default()
+        }
+    }
+
+    /**
+    Run command(s)
+
+    If [`Shell::batch_timeout`] is set and exceeded, the running command(s) are killed and any
+    remaining commands are not run.
+    Set [`Shell::process_group`] to also kill any processes the command started.
+
+    If [`Shell::sync`] is not set, commands run in parallel via rayon; without the `parallel`
+    feature (enabled by default), they run one after the other without printing fences.
+
+    Each result starts as a clone of its command; use [`Shell::run_owned`] to move the commands
+    into their results instead.
+
+    ```
+    use {sprint::*, std::time::Duration};
+
+    let shell = Shell {
+        batch_timeout: Some(Duration::from_millis(500)),
+        process_group: true,
+        ..Default::default()
+    };
+
+    let results = shell.run(&[Command::new("true"), Command::new("sleep 5"), Command::new("true")]);
+
+    assert_eq!(results.len(), 2);
+    assert_eq!(results[0].code, Some(0));
+    assert_eq!(results[1].code, None);
+    ```
+    */
+    pub fn run(&self, commands: &[Command]) -> Vec<Command> {
+        let commands = commands.iter().map(Cow::Borrowed);
+        if self.sync {
+            self.run_sync(commands)
+        } else {
+            self.run_parallel(commands.collect())
+        }
+    }
+
+    /// Run command(s) in parallel, or one after the other without the `parallel` feature
+    fn run_parallel(&self, commands: Vec<Cow<Command>>) -> Vec<Command> {
+        #[cfg(feature = "parallel")]
+        use rayon::prelude::*;
+
+        #[cfg(feature = "parallel")]
+        let commands = commands.into_par_iter();
+        #[cfg(not(feature = "parallel"))]
+        let commands = commands.into_iter();
+
+        let deadline = self.deadline();
+        let (r, errors): (Vec<_>, Vec<_>) = commands
+            .enumerate()
+            .map(
+                |(i, mut command)| match self.run1_until(&mut command, deadline, Some(i)) {
+                    Ok(result) => (result, None),
+                    Err(e) => (command.into_owned(), Some(e.to_string())),
+                },
+            )
+            .unzip();
+
+        if self.print {
+            self.print_errors(&errors.into_iter().flatten().collect::<Vec<_>>(), &r);
+        }
+
+        r
+    }
+
+    /// Run a command of a parallel batch; an error running it is printed and the command is
+    /// returned as is
+    fn run1_parallel(&self, command: &Command, deadline: Option<Instant>, index: usize) -> Command {
+        self.run1_until(&mut Cow::Borrowed(command), deadline, Some(index))
+            .unwrap_or_else(|e| {
+                if self.print {
+                    self.emit(format_args!(
+                        "{}",
+                        self.active_renderer().error(self, &e.to_string())
                     ));
                 }
+                command.clone()
+            })
+    }
+
+    /**
+    Run command(s) in parallel and call `f` with each result as soon as its command finishes
+
+    Commands run in parallel via rayon regardless of [`Shell::sync`]; results are passed to `f` in
+    the order the commands finish.
+    A command that cannot be run, e.g. due to unbalanced quotes, is passed to `f` as is after its
+    error is printed.
+    Without the `parallel` feature (enabled by default), they run one after the other.
+
+    ```
+    use sprint::*;
+
+    let shell = Shell {
+        print: false,
+        ..Default::default()
+    };
+
+    let mut codes = vec![];
+
+    shell.run_async_each(&["exit 1".into(), "exit 2".into()], |result| {
+        codes.push(result.code.unwrap());
+    });
+
+    codes.sort();
+    assert_eq!(codes, vec![1, 2]);
+    ```
+    */
+    pub fn run_async_each(&self, commands: &[Command], mut f: impl FnMut(Command)) {
+        let deadline = self.deadline();
+
+        #[cfg(feature = "parallel")]
+        {
+            use rayon::prelude::*;
+
+            let (tx, rx) = std::sync::mpsc::channel();
+
+            // Run the commands on another thread so results are received here as they finish
+            std::thread::scope(|s| {
+                s.spawn(move || {
+                    commands
+                        .par_iter()
+                        .enumerate()
+                        .for_each_with(tx, |tx, (i, command)| {
+                            tx.send(self.run1_parallel(command, deadline, i))
+                                .expect("send");
+                        });
+                });
+
+                for result in rx {
+                    f(result);
+                }
+            });
+        }
+
+        #[cfg(not(feature = "parallel"))]
+        for (i, command) in commands.iter().enumerate() {
+            f(self.run1_parallel(command, deadline, i));
+        }
+    }
+
+    /**
+    Run command(s) one after the other with each command's stdin set to the previous command's
+    captured stdout
+
+    Unlike a shell pipeline, each command's stdout is fully buffered in memory before the next
+    command starts, so every stage's output can be inspected via [`Pipe::String`] in the results.
+    Avoid it for large outputs.
+    Stops after a command fails unless [`Shell::keep_going`] is set.
+
+    ```
+    use sprint::*;
+
+    let shell = Shell {
+        print: false,
+        ..Default::default()
+    };
+
+    let results = shell.run_piped(&["printf 'b\\na\\nb\\n'".into(), "sort".into(), "uniq".into()]);
+
+    assert_eq!(results[1].stdout, Pipe::String(Some(String::from("a\nb\nb\n"))));
+    assert_eq!(results[2].stdout, Pipe::String(Some(String::from("a\nb\n"))));
+    ```
+    */
+    pub fn run_piped(&self, commands: &[Command]) -> Vec<Command> {
+        // Run each command as its own batch without a summary
+        let shell = Shell {
+            keep_going: false,
+            ..self.clone()
+        };
+
+        let mut r: Vec<Command> = vec![];
+
+        for command in commands {
+            let mut command = Command {
+                stdout: Pipe::string(),
+                ..command.clone()
+            };
+            if let Some(previous) = r.last() {
+                command.stdin = Pipe::String(Some(previous.stdout.clone().into_string()));
+            }
+
+            let result = shell.run(&[command]).remove(0);
+            let failed = !result.succeeded();
+            r.push(result);
+
+            if failed && !self.keep_going {
+                break;
+            }
+        }
+
+        r
+    }
+
+    /**
+    Run sections of command(s), each as its own batch after a header with its title
+
+    Returns the results of each section that ran.
+    A failed section stops the remaining sections unless [`Shell::keep_going`] is set.
+
+    ```
+    use sprint::*;
+
+    let shell = Shell {
+        print: false,
+        ..Default::default()
+    };
+
+    let results = shell.run_sections(&[
+        (String::from("Setup"), vec![Command::new("true")]),
+        (String::from("Build"), vec![Command::new("exit 2")]),
+        (String::from("Test"), vec![Command::new("true")]),
+    ]);
+
+    assert_eq!(results.len(), 2);
+    assert_eq!(results[1][0].code, Some(2));
+    ```
+    */
+    pub fn run_sections(&self, sections: &[(String, Vec<Command>)]) -> Vec<Vec<Command>> {
+        let mut r = vec![];
+
+        for (title, commands) in sections {
+            if self.print {
+                self.emit(format_args!(
+                    "{}",
+                    self.active_renderer().section(self, title)
+                ));
+            }
+
+            let results = self.run(commands);
+            let failed = results.len() < commands.len() || !results_ok(&results);
+            r.push(results);
+
+            if failed && !self.keep_going {
+                break;
+            }
+        }
+
+        r
+    }
+
+    /**
+    Run command(s) one after the other until one fails
+
+    Returns the results of the commands that succeeded and the result of the failed command, if
+    any.
+
+    ```
+    use sprint::*;
+
+    let shell = Shell::default();
+
+    let (results, failed) = shell.run_until_error(&["true".into(), "exit 2".into(), "true".into()]);
+
+    assert_eq!(results.len(), 1);
+    assert_eq!(failed.unwrap().code, Some(2));
+    ```
+    */
+    pub fn run_until_error(&self, commands: &[Command]) -> (Vec<Command>, Option<Command>) {
+        let shell = Shell {
+            sync: true,
+            keep_going: false,
+            ..self.clone()
+        };
+
+        let mut results = shell.run(commands);
+        let failed = results
+            .iter()
+            .position(|x| !x.succeeded())
+            .map(|i| results.remove(i));
+
+        (results, failed)
+    }
+
+    /**
+    Run a target after its dependencies; see [`parse_targets`]
+
+    Dependencies are run first in dependency order and each target is run at most once.
+    Stops at the first target with a failed command unless [`Shell::keep_going`] is set.
+
+    Returns an error listing the available targets if a target does not exist, or naming the loop
+    if the dependencies have a cycle.
+
+    ```
+    use sprint::*;
+
+    let targets = parse_targets(
+        "# all\n\n* hello\n\n# hello\n\n```\necho hello\n```\n\n# loop\n\n* loop\n",
+    );
+
+    let shell = Shell {
+        print: false,
+        ..Default::default()
+    };
+
+    assert_eq!(shell.run_target(&targets, "all").unwrap()[0].code, Some(0));
+    assert_eq!(
+        shell.run_target(&targets, "build").unwrap_err().to_string(),
+        "Target `build` not found! Available targets: `all`, `hello`, `loop`.",
+    );
+    assert_eq!(
+        shell.run_target(&targets, "loop").unwrap_err().to_string(),
+        "Dependency cycle: `loop` -> `loop`!",
+    );
+    ```
+    */
+    pub fn run_target(
+        &self,
+        targets: &BTreeMap<String, Target>,
+        target: &str,
+    ) -> Result<Vec<Command>> {
+        let mut order = vec![];
+        resolve_target(targets, target, &mut vec![], &mut order)?;
+
+        let mut r = vec![];
+        for name in order {
+            let results = self.run(&targets[name].commands);
+            let ok = results_ok(&results);
+            r.extend(results);
+            if !ok && !self.keep_going {
+                break;
+            }
+        }
+
+        Ok(r)
+    }
+
+    /**
+    Run command(s) from an iterator
+
+    In sync mode, commands are consumed lazily so each command starts as soon as it is yielded.
+    Accepts anything that converts into a [`Command`], e.g. strings.
+
+    ```
+    use sprint::*;
+
+    let shell = Shell::default();
+
+    shell.run_iter((1..=3).map(|i| Command::new(&format!("echo {i}"))));
+    shell.run_iter(["echo a", "echo b"]);
+    ```
+    */
+    pub fn run_iter(&self, commands: impl IntoIterator<Item = impl Into<Command>>) -> Vec<Command> {
+        let commands = commands.into_iter().map(Into::into);
+        if self.sync {
+            self.run_sync(commands.map(Cow::Owned))
+        } else {
+            self.run(&commands.collect::<Vec<_>>())
+        }
+    }
+
+    /**
+    Run command(s) like [`Shell::run`] but consume them
+
+    Each command is moved into its result instead of cloned, which avoids copying large commands
+    and stdin, whether they run one after the other or in parallel; with a
+    [`Shell::batch_timeout`], only a string stdin is moved.
+
+    ```
+    use sprint::*;
+
+    let shell = Shell {
+        print: false,
+        ..Default::default()
+    };
+
+    let input = "line\n".repeat(100_000);
+
+    let results = shell.run_owned(vec![Command {
+        stdin: Pipe::String(Some(input)),
+        stdout: Pipe::string(),
+        ..Command::new("wc -l")
+    }]);
+
+    assert_eq!(results[0].stdout.clone().into_string().trim(), "100000");
+    assert!(matches!(&results[0].stdin, Pipe::String(Some(s)) if s.len() == 500_000));
+    ```
+    */
+    pub fn run_owned(&self, commands: Vec<Command>) -> Vec<Command> {
+        let commands = commands.into_iter().map(Cow::Owned);
+        if self.sync {
+            self.run_sync(commands)
+        } else {
+            self.run_parallel(commands.collect())
+        }
+    }
+
+    /// Run command(s) one after the other
+    fn run_sync<'a>(&self, commands: impl Iterator<Item = Cow<'a, Command>>) -> Vec<Command> {
+        if self.print && self.decorate && !self.fence_empty {
+            return self.run_buffered(commands);
+        }
+
+        let n = exact_len(&commands);
+        let deadline = self.deadline();
+
+        // Info of the current block
+        let mut info: Option<String> = None;
+
+        let mut r = vec![];
+        let mut errors = vec![];
+
+        for (i, mut command) in commands.enumerate() {
+            self.print_block(&mut info, &command);
+            self.show_progress(i, n, &command);
+
+            match self.run1_until(&mut command, deadline, Some(i)) {
+                Ok(result) => {
+                    errors.extend(self.error(&result));
+                    r.push(result);
+                }
+                Err(e) => {
+                    errors.push(self.failed(e, r.len(), deadline));
+                    r.push(command.into_owned());
+                }
+            }
+
+            if self.stop(&errors, deadline) {
+                break;
+            }
+        }
+
+        self.print_end(info, &errors, &r);
+
+        r
+    }
+
+    /// Print the fence and info before a command if it starts a new block, otherwise a blank line
+    fn print_block(&self, info: &mut Option<String>, command: &Command) {
+        if self.print && self.decorate {
+            let command_info = self.command_info(command);
+            match info {
+                None => {
+                    self.print_open(command_info);
+                }
+                Some(current) if current != command_info => {
+                    // Start a new block with the command's info
+                    self.print_close();
+                    self.print_open(command_info);
+                }
+                Some(_current) => {
+                    if !self.dry_run {
+                        self.emit(format_args!("{}", self.active_renderer().separator(self)));
+                    }
+                }
+            }
+            *info = Some(command_info.to_string());
+        }
+    }
+
+    /// Print the closing fence and errors at the end of a batch
+    fn print_end(&self, info: Option<String>, errors: &[String], results: &[Command]) {
+        if self.print {
+            if self.decorate {
+                if info.is_none() {
+                    self.print_open(&self.info);
+                }
+                self.print_close();
+            }
+
+            self.print_errors(errors, results);
+        }
+    }
+
+    /// Run command(s) and print the fence only if the commands produce output
+    ///
+    /// Each block's commands are held back until the first output or stdin of the block, then
+    /// printed with the output as it arrives.
+    fn run_buffered<C: Borrow<Command>>(&self, commands: impl Iterator<Item = C>) -> Vec<Command> {
+        let n = exact_len(&commands);
+        let deadline = self.deadline();
+
+        // Stdin is printed below so it stays in order with the held back commands
+        let quiet = Shell {
+            decorate: false,
+            ..self.clone()
+        };
+
+        // Current block: info, transcript not printed yet, and whether the fence is open
+        let mut block: Option<(String, String, bool)> = None;
+        let mut forwarded = Forwarded::default();
+
+        let mut r = vec![];
+        let mut errors = vec![];
+
+        for (i, command) in commands.enumerate() {
+            let command = command.borrow();
+
+            self.show_progress(i, n, command);
+
+            let info = self.command_info(command);
+            match &mut block {
+                Some((current, transcript, open)) if current == info => {
+                    if !self.dry_run {
+                        let separator = self.active_renderer().separator(self);
+                        if *open {
+                            self.emit(format_args!("{separator}"));
+                        } else {
+                            transcript.push_str(&separator);
+                        }
+                    }
+                }
+                _ => {
+                    self.finish_block(block.take());
+                    block = Some((info.to_string(), String::new(), false));
+                }
+            }
+            let (info, transcript, open) = block.as_mut().unwrap();
+
+            let formatted = self.format_command(command);
+            if *open {
+                self.emit(format_args!("{formatted}"));
+            } else {
+                transcript.push_str(&formatted);
+            }
+
+            let result = if self.dry_run {
+                Ok(dry_run(command.clone()))
+            } else {
+                if let Pipe::String(Some(s)) = &command.stdin {
+                    self.open_block(info, transcript, open);
+                    self.print_close();
+                    self.print_open(&command.command);
+                    self.emitln(format_args!("{s}"));
+                    self.print_close();
+                    self.print_open(info);
+                }
+
+                quiet.core_forward(
+                    &mut Cow::Borrowed(command),
+                    deadline,
+                    Some(i),
+                    Some(&mut |chunk| {
+                        self.open_block(info, transcript, open);
+                        self.print_output(chunk, &mut forwarded);
+                    }),
+                )
+            };
+
+            match result {
+                Ok(result) => {
+                    errors.extend(self.error(&result));
+                    r.push(result);
+                }
+                Err(e) => {
+                    errors.push(self.failed(e, r.len(), deadline));
+                    r.push(command.clone());
+                }
+            }
+
+            if self.stop(&errors, deadline) {
+                break;
+            }
+        }
+
+        self.finish_block(block);
+        self.print_errors(&errors, &r);
+
+        r
+    }
+
+    /// Open the fence of a block of [`Shell::run_buffered`] and print its held back transcript
+    fn open_block(&self, info: &str, transcript: &mut String, open: &mut bool) {
+        if !*open {
+            self.print_open(info);
+            self.emit(format_args!("{transcript}"));
+            transcript.clear();
+            *open = true;
+        }
+    }
+
+    /// Close the fence of a block of [`Shell::run_buffered`], or print its transcript without a
+    /// fence if it had no output
+    fn finish_block(&self, block: Option<(String, String, bool)>) {
+        match block {
+            Some((_info, _transcript, true)) => self.print_close(),
+            Some((_info, transcript, false)) => self.emitln(format_args!("{transcript}")),
+            None => {}
+        }
+    }
+
+    /// Print a chunk of a command's output with each line prefixed by [`Shell::output_prefix`]
+    ///
+    /// Goes to [`Shell::out`] if set, otherwise to the stream the chunk came from regardless of
+    /// [`Shell::decorations_to_stderr`].
+    fn print_output(&self, chunk: OutputChunk, forwarded: &mut Forwarded) {
+        let (i, mut bytes) = match chunk {
+            OutputChunk::Stdout(x) => (0, x),
+            OutputChunk::Stderr(x) => (1, x),
+            OutputChunk::Exit(_code) => return,
+        };
+
+        // Hold back an incomplete UTF-8 sequence until the next chunk
+        let mut partial = std::mem::take(&mut forwarded.partial[i]);
+        partial.append(&mut bytes);
+        if let Err(e) = std::str::from_utf8(&partial) {
+            if e.error_len().is_none() {
+                forwarded.partial[i] = partial.split_off(e.valid_up_to());
+            }
+        }
+        let mut s = String::from_utf8_lossy(&partial).into_owned();
+
+        if let Some(prefix) = &self.output_prefix {
+            let mut prefixed = String::new();
+            for line in s.split_inclusive('\n') {
+                if !forwarded.mid_line[i] {
+                    prefixed.push_str(prefix);
+                }
+                prefixed.push_str(line);
+                forwarded.mid_line[i] = !line.ends_with('\n');
+            }
+            s = prefixed;
+        }
+
+        let s = self.active_renderer().output(self, &s);
+        if self.out.is_some() {
+            self.emit(format_args!("{s}"));
+        } else if i == 0 {
+            let mut stdout =
+                anstream::AutoStream::new(std::io::stdout().lock(), self.color.choice());
+            stdout.write_all(s.as_bytes()).expect("write");
+            stdout.flush().expect("flush");
+        } else {
+            anstream::AutoStream::new(std::io::stderr().lock(), self.color.choice())
+                .write_all(s.as_bytes())
+                .expect("write");
+        }
+    }
+
+    /// Run command(s) and return the session as Markdown instead of printing it
+    ///
+    /// Each command and its output is placed in its own fenced code block, with the stdout before
+    /// the stderr rather than interleaved.
+    /// Stops at the first failure unless [`Shell::keep_going`] is set.
+    pub fn run_markdown(&self, commands: &[Command]) -> String {
+        let shell = Shell {
+            print: false,
+            ..self.clone()
+        };
+
+        let deadline = self.deadline();
+        let mut r = String::new();
+        let mut errors = vec![];
+
+        for (i, command) in commands.iter().enumerate() {
+            r.push_str(&format!(
+                "{}{}\n{}{}\n",
+                self.fence,
+                self.command_info(command),
+                self.prompt,
+                self.wrap_command(&command.command, true),
+            ));
+
+            let result = if self.dry_run {
+                Ok(dry_run(command.clone()))
+            } else {
+                shell
+                    .run_captured(command, deadline, Some(i))
+                    .map(|(result, s)| {
+                        r.push_str(&s);
+                        if !s.is_empty() && !s.ends_with('\n') {
+                            r.push('\n');
+                        }
+                        result
+                    })
+            };
+
+            r.push_str(&format!("{}\n\n", self.fence));
+
+            let error = match result {
+                Ok(result) => self.error(&result),
+                Err(e) => Some(self.failed(e, i, deadline)),
+            };
+
+            if let Some(error) = error {
+                r.push_str(&format!("**{error}**\n\n"));
+                errors.push(error);
+            }
+
+            if self.stop(&errors, deadline) {
+                break;
+            }
+        }
+
+        r
+    }
+
+    /// Run a single command and capture the output that would go to the terminal
+    ///
+    /// Returns the result with the original pipes and the captured output with each line prefixed by
+    /// [`Shell::output_prefix`] if set.
+    fn run_captured(
+        &self,
+        command: &Command,
+        deadline: Option<Instant>,
+        index: Option<usize>,
+    ) -> Result<(Command, String)> {
+        let mut captured = command.clone();
+        if captured.stdout.is_inherit() {
+            captured.stdout = Pipe::string();
+        }
+        if captured.stderr.is_inherit() {
+            captured.stderr = Pipe::string();
+        }
+
+        let mut result = self.core_until(&mut Cow::Owned(captured), deadline, index)?;
+        let mut output = String::new();
+
+        for (pipe, original) in [
+            (&mut result.stdout, &command.stdout),
+            (&mut result.stderr, &command.stderr),
+        ] {
+            if original.is_inherit() {
+                if let Pipe::String(Some(s)) = pipe {
+                    output.push_str(s);
+                }
+                *pipe = original.clone();
+            }
+        }
+
+        if let Some(prefix) = &self.output_prefix {
+            output = output
+                .split_inclusive('\n')
+                .map(|line| format!("{prefix}{line}"))
+                .collect();
+        }
+
+        Ok((result, output))
+    }
+
+    /// Show the progress of a batch on stderr
+    ///
+    /// Requires the `indicatif` feature; hidden if stderr is not a terminal.
+    #[allow(unused_variables)]
+    fn show_progress(&self, i: usize, n: Option<usize>, command: &Command) {
+        #[cfg(feature = "indicatif")]
+        if self.progress && self.print {
+            let pb = indicatif::ProgressBar::with_draw_target(
+                n.map(|x| x as u64),
+                indicatif::ProgressDrawTarget::stderr(),
+            );
+            pb.set_style(
+                indicatif::ProgressStyle::with_template(if n.is_some() {
+                    "[{pos}/{len}] {msg}"
+                } else {
+                    "[{pos}] {msg}"
+                })
+                .unwrap(),
+            );
+            pb.set_position(i as u64 + 1);
+            pb.set_message(command.command.clone());
+
+            // Leave the progress line above the command's output
+            pb.abandon();
+        }
+    }
+
+    /**
+    Run command(s) and return exactly what [`Shell::run`] prints while still printing it
+
+    Colors are included or not per [`Shell::color`] and the destination.
+
+    ```
+    use sprint::*;
+
+    let shell = Shell {
+        color: ColorOverride::Never,
+        ..Default::default()
+    };
+
+    assert_eq!(shell.render(&[Command::new("echo hello")]), "```text\n$ echo hello\nhello\n```\n\n");
+    ```
+    */
+    pub fn render(&self, commands: &[Command]) -> String {
+        let color = match self.color {
+            ColorOverride::Auto => {
+                let choice = if self.out.is_some() {
+                    anstream::ColorChoice::Never
+                } else if self.decorations_to_stderr {
+                    anstream::AutoStream::choice(&std::io::stderr())
+                } else {
+                    anstream::AutoStream::choice(&std::io::stdout())
+                };
+                if choice == anstream::ColorChoice::Never {
+                    ColorOverride::Never
+                } else {
+                    ColorOverride::Always
+                }
+            }
+            ref color => color.clone(),
+        };
+
+        let buffer = Arc::new(Mutex::new(vec![]));
+
+        let shell = Shell {
+            color,
+            out: Some(Writer::new(Tee {
+                buffer: buffer.clone(),
+                out: self.out.clone(),
+                stderr: self.decorations_to_stderr,
+            })),
+            ..self.clone()
+        };
+
+        shell.run(commands);
+
+        let buffer = buffer.lock().expect("lock");
+        String::from_utf8_lossy(&buffer).into_owned()
+    }
+
+    /// Check the result of a command and return an error message if it failed
+    fn error(&self, result: &Command) -> Option<String> {
+        if result.succeeded() {
+            return None;
+        }
+
+        match result.code {
+            Some(code) => Some(format!(
+                "Command `{}` exited with code: `{code}`!",
+                result.command,
+            )),
+            None => Some(match result.signal {
+                Some(signal) => format!(
+                    "Command `{}` was killed by {}!",
+                    result.command,
+                    signal_name(signal),
+                ),
+                None => format!("Command `{}` was killed by a signal!", result.command),
+            }),
+        }
+    }
+
+    /// Get the info for a command; falls back to the shell's info
+    fn command_info<'a>(&'a self, command: &'a Command) -> &'a str {
+        command.info.as_deref().unwrap_or(&self.info)
+    }
+
+    /// Get the renderer in use; see [`Shell::renderer`]
+    fn active_renderer(&self) -> &dyn Renderer {
+        self.renderer.as_deref().unwrap_or(&MarkdownRenderer)
+    }
+
+    /// Format the prompt and command for printing
+    fn format_command(&self, command: &Command) -> String {
+        self.active_renderer().command(self, command)
+    }
+
+    /// Wrap a command at its operators (see [`Shell::wrap_operators`]) and at
+    /// [`Shell::wrap_width`]
+    fn wrap_command(&self, command: &str, prompt: bool) -> String {
+        let command = wrap(command, self.wrap_operators);
+
+        let Some(width) = self.wrap_width() else {
+            return command;
+        };
+
+        let mut lines = vec![];
+        for (i, line) in command.lines().enumerate() {
+            // Account for the prompt on the first line
+            let mut available = if i == 0 && prompt {
+                width.saturating_sub(self.prompt.chars().count())
+            } else {
+                width
+            };
+
+            let mut current: Option<String> = None;
+            for word in line.split(' ') {
+                current = Some(match current {
+                    None => word.to_string(),
+                    Some(current)
+                        if !current.trim().is_empty()
+                            && current.chars().count() + word.chars().count() + 3 > available =>
+                    {
+                        // Continue on the next line
+                        lines.push(format!("{current} \\"));
+                        available = width;
+                        format!("{WRAP_INDENT}{word}")
+                    }
+                    Some(current) => format!("{current} {word}"),
+                });
+            }
+            lines.push(current.unwrap_or_default());
+        }
+
+        lines.join("\n")
+    }
+
+    /// Get the width for wrapping commands; see [`Shell::wrap_width`]
+    fn wrap_width(&self) -> Option<usize> {
+        match self.wrap_width {
+            Some(0) => std::env::var("COLUMNS")
+                .ok()
+                .and_then(|x| x.parse().ok())
+                .or_else(|| terminal_size::terminal_size().map(|(w, _h)| w.0 as usize)),
+            width => width,
+        }
+    }
+
+    /// Run a single command
+    pub fn run1(&self, command: &Command) -> Result<Command> {
+        self.run1_until(&mut Cow::Borrowed(command), None, None)
+    }
+
+    /// Run a single command; kill it and return an error if it is still running at the deadline
+    ///
+    /// The index of the command in its batch names its output files; see [`Shell::output_files`].
+    fn run1_until(
+        &self,
+        command: &mut Cow<Command>,
+        deadline: Option<Instant>,
+        index: Option<usize>,
+    ) -> Result<Command> {
+        if deadline.is_some_and(|x| Instant::now() >= x) {
+            return Err(anyhow!("Deadline exceeded!"));
+        }
+
+        if self.print && (self.decorate || self.dry_run) {
+            self.emit(format_args!("{}", self.format_command(command)));
+        }
+
+        if self.dry_run {
+            return Ok(dry_run(take_command(command)));
+        }
+
+        if self.print && (self.out.is_some() || self.output_prefix.is_some()) {
+            // Forward the command's output so it can be prefixed and written to the custom writer
+            let mut forwarded = Forwarded::default();
+            return self.core_forward(
+                command,
+                deadline,
+                index,
+                Some(&mut |chunk| self.print_output(chunk, &mut forwarded)),
+            );
+        }
+
+        self.core_until(command, deadline, index)
+    }
+
+    /// True if a batch should stop; i.e. a command failed and [`Shell::keep_going`] is not set, or
+    /// the deadline was exceeded
+    fn stop(&self, errors: &[String], deadline: Option<Instant>) -> bool {
+        !errors.is_empty() && (!self.keep_going || deadline.is_some_and(|x| Instant::now() >= x))
+    }
+
+    /// Print the errors of a batch and a [`Summary`] if [`Shell::keep_going`] is set
+    fn print_errors(&self, errors: &[String], results: &[Command]) {
+        for error in errors {
+            self.emit(format_args!(
+                "{}",
+                self.active_renderer().error(self, error)
+            ));
+        }
+
+        if self.keep_going && !self.dry_run {
+            let summary = Summary::new(results);
+            let style = if summary.failed == 0 {
+                self.info_style
+            } else {
+                self.error_style
+            };
+            self.emitln(format_args!("{}\n", summary.style(style)));
+        }
+    }
+
+    /// Get the deadline for a batch per [`Shell::batch_timeout`]
+    fn deadline(&self) -> Option<Instant> {
+        self.batch_timeout.map(|x| Instant::now() + x)
+    }
+
+    /// Format the error for a command that failed to run
+    fn failed(&self, e: anyhow::Error, completed: usize, deadline: Option<Instant>) -> String {
+        match (self.batch_timeout, deadline) {
+            (Some(timeout), Some(deadline)) if Instant::now() >= deadline => format!(
+                "Batch timeout of {timeout:?} exceeded after {completed} command(s) completed!"
+            ),
+            _ => e.to_string(),
+        }
+    }
+
+    /**
+    Run a single command and capture its exit code, stdout, and stderr
+
+    Always captures both streams and never prints regardless of [`Shell::print`].
+    Returns an empty successful [`Output`] if [`Shell::dry_run`] is set.
+
+    ```
+    use sprint::*;
+
+    let output = Shell::default().capture("echo hello; echo world >&2").unwrap();
+
+    assert_eq!(output.code, Some(0));
+    assert_eq!(output.stdout, "hello\n");
+    assert_eq!(output.stderr, "world\n");
+    ```
+    */
+    pub fn capture(&self, command: &str) -> Result<Output> {
+        if self.dry_run {
+            return Ok(Output {
+                code: Some(0),
+                ..Default::default()
+            });
+        }
+
+        let shell = Shell {
+            print: false,
+            ..self.clone()
+        };
+
+        let result = shell.core(Command {
+            command: command.to_string(),
+            stdout: Pipe::string(),
+            stderr: Pipe::string(),
+            ..Default::default()
+        })?;
+
+        Ok(Output {
+            code: result.code,
+            stdout: result.stdout.into_string(),
+            stderr: result.stderr.into_string(),
+            pid: result.pid,
+        })
+    }
+
+    /**
+    Run a single command with output discarded and return whether it succeeded
+
+    Never prints regardless of [`Shell::print`]; returns `true` if [`Shell::dry_run`] is set.
+
+    ```
+    use sprint::*;
+
+    let shell = Shell::default();
+
+    assert!(shell.succeeds("command -v sh"));
+    assert!(!shell.succeeds("command -v not-a-real-command"));
+    ```
+    */
+    pub fn succeeds(&self, command: &str) -> bool {
+        if self.dry_run {
+            return true;
+        }
+
+        let shell = Shell {
+            print: false,
+            ..self.clone()
+        };
+
+        match shell.core(Command {
+            command: command.to_string(),
+            stdout: Pipe::Null,
+            stderr: Pipe::Null,
+            ..Default::default()
+        }) {
+            Ok(result) => result.is_success(result.code),
+            Err(_e) => false,
+        }
+    }
+
+    /**
+    Run a single command and return its stdout if it succeeded, otherwise an error with the
+    command, exit code, and stderr
+
+    Always captures both streams and never prints regardless of [`Shell::print`].
+    Returns an empty string if [`Shell::dry_run`] is set.
+
+    ```
+    use sprint::*;
+
+    let shell = Shell::default();
+
+    assert_eq!(shell.check("echo hello").unwrap(), "hello\n");
+
+    let e = shell.check("echo oops >&2; exit 2").unwrap_err();
+    assert_eq!(e.to_string(), "Command `echo oops >&2; exit 2` exited with code: `2`!\noops\n");
+    ```
+    */
+    pub fn check(&self, command: &str) -> Result<String> {
+        if self.dry_run {
+            return Ok(String::new());
+        }
+
+        let shell = Shell {
+            print: false,
+            ..self.clone()
+        };
+
+        let result = shell.core(Command {
+            command: command.to_string(),
+            stdout: Pipe::string(),
+            stderr: Pipe::string(),
+            ..Default::default()
+        })?;
+
+        match (shell.error(&result), result.stderr.into_string()) {
+            (None, _) => Ok(result.stdout.into_string()),
+            (Some(e), stderr) if stderr.is_empty() => Err(anyhow!(e)),
+            (Some(e), stderr) => Err(anyhow!("{e}\n{stderr}")),
+        }
+    }
+
+    /**
+    Locate an executable program in the `PATH` environment variable
+
+    A program containing a path separator is checked as is.
+    On Windows, the extensions in the `PATHEXT` environment variable are also tried.
+
+    ```
+    use sprint::*;
+
+    let shell = Shell::default();
+
+    assert!(shell.which("sh").is_some_and(|x| x.ends_with("sh")));
+    assert!(shell.which("not-a-real-command").is_none());
+    ```
+    */
+    pub fn which(&self, program: &str) -> Option<std::path::PathBuf> {
+        let path = std::path::Path::new(program);
+        if program.is_empty() {
+            return None;
+        } else if path.components().count() > 1 {
+            return is_executable(path).then(|| path.to_path_buf());
+        }
+
+        let mut names = vec![program.to_string()];
+        if cfg!(windows) {
+            let exts =
+                std::env::var("PATHEXT").unwrap_or_else(|_| String::from(".COM;.EXE;.BAT;.CMD"));
+            names.extend(
+                exts.split(';')
+                    .filter(|x| !x.is_empty())
+                    .map(|x| format!("{program}{x}")),
+            );
+        }
+
+        std::env::split_paths(&std::env::var_os("PATH")?).find_map(|dir| {
+            names
+                .iter()
+                .map(|name| dir.join(name))
+                .find(|x| is_executable(x))
+        })
+    }
+
+    /**
+    Run a single command with the given input on stdin and capture its stdout
+
+    The result's code is `None` if the command could not be run.
+
+    ```
+    use sprint::*;
+
+    let result = Shell::default().run_with_stdin("sort", "b\na\n");
+
+    assert_eq!(result.stdout, Pipe::String(Some(String::from("a\nb\n"))));
+    ```
+    */
+    pub fn run_with_stdin(&self, command: &str, input: &str) -> Command {
+        let command = Command {
+            command: command.to_string(),
+            stdin: Pipe::String(Some(input.to_string())),
+            stdout: Pipe::string(),
+            ..Default::default()
+        };
+
+        self.core(&command).unwrap_or(command)
+    }
+
+    /**
+    Run a single command with lines from an iterator written to stdin as they are produced and
+    capture its stdout
+
+    Each line is written with a trailing newline and flushed; stdin is closed once the iterator
+    ends, or early if the command stops reading.
+    The result's code is `None` if the command could not be run.
+
+    ```
+    use sprint::*;
+
+    let lines = (1..=3).map(|i| format!("line {i}"));
+
+    let result = Shell::default().run_stdin_lines("grep -v 2", lines);
+
+    assert_eq!(result.stdout, Pipe::String(Some(String::from("line 1\nline 3\n"))));
+    ```
+    */
+    pub fn run_stdin_lines(&self, command: &str, lines: impl Iterator<Item = String>) -> Command {
+        let command = Command {
+            command: command.to_string(),
+            stdin: Pipe::string(),
+            stdout: Pipe::string(),
+            ..Default::default()
+        };
+
+        if let Some(before) = &self.before {
+            (before.0)(&command);
+        }
+
+        let mut running = match self.spawn(&command) {
+            Ok(running) => running,
+            Err(_e) => return command,
+        };
+
+        // Read stdout in the background so the command cannot block on a full pipe
+        let stdout = read_thread(
+            running.stdout.take().unwrap(),
+            command.capture_limit,
+            command.tail,
+            Default::default(),
+        );
+
+        if let Some(mut stdin) = running.stdin.take() {
+            for line in lines {
+                if writeln!(stdin, "{line}")
+                    .and_then(|()| stdin.flush())
+                    .is_err()
+                {
+                    // The command stopped reading
+                    break;
+                }
+            }
+        }
+
+        let (stdout, truncated) = stdout.join().expect("join");
+        let mut r = Command {
+            stdout: Pipe::String(Some(stdout)),
+            truncated,
+            ..std::mem::take(&mut running.command)
+        };
+        if let Ok(status) = running.child.wait() {
+            r.code = status.code();
+            r.signal = exit_signal(status);
+        }
+
+        if let Some(after) = &self.after {
+            (after.0)(&r);
+        }
+
+        r
+    }
+
+    /**
+    Pipe a single command and return its stdout exactly as written, including the trailing newline
+
+    Use [`Shell::pipe1_trimmed`] to capture a single value like a version or hash.
+
+    ```
+    use sprint::*;
+
+    assert_eq!(Shell::default().pipe1("echo hello"), "hello\n");
+    ```
+    */
+    pub fn pipe1(&self, command: &str) -> String {
+        let command = Command {
+            command: command.to_string(),
+            stdout: Pipe::string(),
+            ..Default::default()
+        };
+
+        if let Ok(Command {
+            stdout: Pipe::String(Some(stdout)),
+            ..
+        }) = self.core(command)
+        {
+            stdout
+        } else {
+            String::new()
+        }
+    }
+
+    /**
+    Pipe a single command and return its stdout without trailing whitespace
+
+    Unlike [`Shell::pipe1`], the trailing newline and any other trailing whitespace is removed.
+
+    ```
+    use sprint::*;
+
+    assert_eq!(Shell::default().pipe1_trimmed("echo ' hello '"), " hello");
+    ```
+    */
+    pub fn pipe1_trimmed(&self, command: &str) -> String {
+        let mut r = self.pipe1(command);
+        r.truncate(r.trim_end().len());
+        r
+    }
+
+    /// Run a command in a child process
+    pub fn run1_async(&self, command: &Command) -> Result<std::process::Child> {
+        self.run1_async_with(command, SpawnOptions::default())
+    }
+
+    /// Run a command in a child process with the given stdio overrides; see [`SpawnOptions`]
+    ///
+    /// A [`Pipe::String`] stdin is only written if stdin is not overridden.
+    pub fn run1_async_with(
+        &self,
+        command: &Command,
+        options: SpawnOptions,
+    ) -> Result<std::process::Child> {
+        let (prog, args) = self.prepare(command)?;
+
+        let mut cmd = std::process::Command::new(&prog);
+        cmd.args(&args);
+
+        let write_stdin = options.stdin.is_none();
+        cmd.stdin(options.stdin.unwrap_or_else(|| command.stdin.stdio()));
+        cmd.stdout(options.stdout.unwrap_or_else(|| command.stdout.stdio()));
+        cmd.stderr(options.stderr.unwrap_or_else(|| command.stderr.stdio()));
+
+        if self.env_clear {
+            cmd.env_clear();
+            cmd.envs(self.kept_env());
+        }
+
+        if self.process_group {
+            // Run the command in its own process group so the whole group can be killed
+            #[cfg(unix)]
+            std::os::unix::process::CommandExt::process_group(&mut cmd, 0);
+
+            #[cfg(windows)]
+            std::os::windows::process::CommandExt::creation_flags(
+                &mut cmd, 0x00000200, // CREATE_NEW_PROCESS_GROUP
+            );
+        }
+
+        #[cfg(unix)]
+        if let Some(f) = pre_exec(command) {
+            unsafe {
+                std::os::unix::process::CommandExt::pre_exec(&mut cmd, f);
+            }
+        }
+
+        if write_stdin {
+            self.print_stdin(command);
+        }
+
+        let mut child = cmd
+            .spawn()
+            .map_err(|e| self.spawn_error(command, &prog, e))?;
+
+        if let (true, Pipe::String(Some(s))) = (write_stdin, &command.stdin) {
+            write_thread(child.stdin.take().unwrap(), s.clone());
+        }
+
+        Ok(child)
+    }
+
+    /// Get the variables in [`Shell::env_keep`] that are set in the parent environment
+    fn kept_env(&self) -> Vec<(&str, std::ffi::OsString)> {
+        self.env_keep
+            .iter()
+            .filter_map(|name| Some((name.as_str(), std::env::var_os(name)?)))
+            .collect()
+    }
+
+    /// Convert `\r\n` to `\n` in a result's captured output; see [`Shell::normalize_newlines`]
+    fn normalize(&self, result: &mut Command) {
+        if result.normalize_newlines.unwrap_or(self.normalize_newlines) {
+            for pipe in [&mut result.stdout, &mut result.stderr] {
+                if let Pipe::String(Some(s)) = pipe {
+                    *s = s.replace("\r\n", "\n");
+                }
+            }
+        }
+    }
+
+    /// Explain an error spawning a command's program, e.g. a missing shell
+    fn spawn_error(&self, command: &Command, prog: &str, e: std::io::Error) -> anyhow::Error {
+        if e.kind() != std::io::ErrorKind::NotFound || self.which(prog).is_some() {
+            e.into()
+        } else if self.uses_shell(command) {
+            anyhow!("Shell program `{prog}` not found; is it installed?")
+        } else {
+            anyhow!("Program `{prog}` not found!")
+        }
+    }
+
+    /// Print a string stdin in its own block
+    fn print_stdin(&self, command: &Command) {
+        if self.print && self.decorate {
+            if let Pipe::String(Some(s)) = &command.stdin {
+                self.print_open(&command.command);
+                self.emitln(format_args!("{s}"));
+                self.print_close();
+                self.print_open(self.command_info(command));
+            }
+        }
+    }
+
+    /**
+    Run a command in a child process and stream its output to a channel
+
+    Reader threads send stdout and stderr as [`OutputChunk`]s as they arrive, followed by a final
+    [`OutputChunk::Exit`].
+    The channel is bounded, so the command blocks on its output until the receiver catches up.
+    The command's stdout and stderr pipes are ignored.
+
+    ```
+    use sprint::*;
+
+    let shell = Shell {
+        print: false,
+        ..Default::default()
+    };
+
+    let (mut stdout, mut stderr, mut code) = (vec![], vec![], None);
+    for chunk in shell.run_channel(&Command::new("echo out; echo err >&2; exit 3")).unwrap() {
+        match chunk {
+            OutputChunk::Stdout(data) => stdout.extend(data),
+            OutputChunk::Stderr(data) => stderr.extend(data),
+            OutputChunk::Exit(c) => code = c,
+        }
+    }
+
+    assert_eq!(stdout, b"out\n");
+    assert_eq!(stderr, b"err\n");
+    assert_eq!(code, Some(3));
+    ```
+    */
+    pub fn run_channel(&self, command: &Command) -> Result<std::sync::mpsc::Receiver<OutputChunk>> {
+        let mut child = self.run1_async_with(
+            command,
+            SpawnOptions {
+                stdout: Some(std::process::Stdio::piped()),
+                stderr: Some(std::process::Stdio::piped()),
+                ..Default::default()
+            },
+        )?;
+
+        let (tx, rx) = std::sync::mpsc::sync_channel(16);
+
+        let stdout = child.stdout.take().unwrap();
+        let stderr = child.stderr.take().unwrap();
+        std::thread::spawn(move || {
+            std::thread::scope(|s| {
+                s.spawn(|| send_chunks(stdout, &tx, OutputChunk::Stdout));
+                s.spawn(|| send_chunks(stderr, &tx, OutputChunk::Stderr));
+            });
+            let code = match child.wait() {
+                Ok(status) => status.code(),
+                Err(_e) => None,
+            };
+            tx.send(OutputChunk::Exit(code)).ok();
+        });
+
+        Ok(rx)
+    }
+
+    /// Run a command in a child process and return a [`RunningCommand`]
+    pub fn spawn(&self, command: &Command) -> Result<RunningCommand> {
+        self.spawn_with(command, SpawnOptions::default())
+    }
+
+    /// Spawn a command and wait for it; moves an owned command into the result instead of
+    /// cloning it, or only its string stdin if there is a deadline, so the command is kept if it
+    /// is killed
+    fn spawn_moving(
+        &self,
+        command: &mut Cow<Command>,
+        deadline: Option<Instant>,
+        wait: impl FnOnce(RunningCommand) -> Result<Command>,
+    ) -> Result<Command> {
+        let Cow::Owned(command) = command else {
+            return wait(self.spawn(command)?);
+        };
+
+        // Print the stdin before it is moved out of the command
+        let s = if let Pipe::String(Some(_)) = command.stdin {
+            self.print_stdin(command);
+            Some(std::mem::replace(&mut command.stdin, Pipe::string()).into_string())
+        } else {
+            None
+        };
+
+        let mut running = match self.run1_async_with(command, SpawnOptions::default()) {
+            // Waiting cannot fail without a deadline
+            Ok(child) if deadline.is_none() => self.running(child, std::mem::take(command)),
+            Ok(child) => self.running(child, command.clone()),
+            Err(e) => {
+                if let Some(s) = s {
+                    command.stdin = Pipe::String(Some(s));
+                }
+                return Err(e);
+            }
+        };
+
+        let writer = s.map(|s| write_thread(running.stdin.take().unwrap(), s));
+        let r = wait(running);
+        let Some(s) = writer.map(|writer| writer.join().expect("join")) else {
+            return r;
+        };
+
+        match r {
+            Ok(mut r) => {
+                r.stdin = Pipe::String(Some(s));
+                Ok(r)
+            }
+            Err(e) => {
+                command.stdin = Pipe::String(Some(s));
+                Err(e)
+            }
+        }
+    }
+
+    /// Run a command in a child process with the given stdio overrides; see [`SpawnOptions`]
+    fn spawn_with(&self, command: &Command, options: SpawnOptions) -> Result<RunningCommand> {
+        let child = self.run1_async_with(command, options)?;
+        Ok(self.running(child, command.clone()))
+    }
+
+    /// Wrap a spawned child process and its command in a [`RunningCommand`]
+    fn running(&self, mut child: std::process::Child, command: Command) -> RunningCommand {
+        RunningCommand {
+            command: Command {
+                pid: Some(child.id()),
+                ..command
+            },
+            stdin: child.stdin.take(),
+            stdout: child.stdout.take(),
+            stderr: child.stderr.take(),
+            process_group: self.process_group,
+            child,
+        }
+    }
+
+    /// Spawn a command with its stdout, and stderr if captured, attached to a pseudo-terminal;
+    /// returns the running command and its output read in the background; see [`Command::pty`]
+    #[cfg(all(unix, feature = "pty"))]
+    fn spawn_pty(&self, command: &Command) -> Result<(RunningCommand, PtyOutput)> {
+        use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+
+        let (mut master, mut slave) = (0, 0);
+        let size = libc::winsize {
+            ws_row: 24,
+            ws_col: 80,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        };
+        let opened = unsafe {
+            libc::openpty(
+                &mut master,
+                &mut slave,
+                std::ptr::null_mut(),
+                std::ptr::null(),
+                &size,
+            )
+        };
+        if opened != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        let (master, slave) =
+            unsafe { (OwnedFd::from_raw_fd(master), OwnedFd::from_raw_fd(slave)) };
+
+        // Keep other child processes from holding the terminal open
+        for fd in [&master, &slave] {
+            unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_SETFD, libc::FD_CLOEXEC) };
+        }
+
+        // Keep newlines as is instead of translating them to `\r\n`
+        let mut termios = unsafe { std::mem::zeroed::<libc::termios>() };
+        if unsafe { libc::tcgetattr(slave.as_raw_fd(), &mut termios) } == 0 {
+            termios.c_oflag &= !libc::OPOST;
+            unsafe { libc::tcsetattr(slave.as_raw_fd(), libc::TCSANOW, &termios) };
+        }
+
+        let merge = matches!(command.stderr, Pipe::String(_));
+        let running = self.spawn_with(
+            &Command {
+                stdout: Pipe::Inherit,
+                stderr: if merge {
+                    Pipe::Inherit
+                } else {
+                    command.stderr.clone()
+                },
+                ..command.clone()
+            },
+            SpawnOptions {
+                stdout: Some(slave.try_clone()?.into()),
+                stderr: if merge {
+                    Some(slave.try_clone()?.into())
+                } else {
+                    None
+                },
+                ..Default::default()
+            },
+        )?;
+
+        // Close the parent's copy so reading ends once the command exits
+        drop(slave);
+
+        let output = PtyOutput::default();
+        let (buffer, done) = (output.buffer.clone(), output.done.clone());
+        let limit = command.capture_limit;
+        std::thread::spawn(move || {
+            let mut master = std::fs::File::from(master);
+            let mut buf = [0; 4096];
+            loop {
+                match master.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(n) => {
+                        let (r, truncated) = &mut *buffer.lock().expect("lock");
+                        r.extend_from_slice(&buf[..n]);
+                        if let Some(limit) = limit.filter(|x| r.len() > *x) {
+                            // Closing the terminal makes the command's writes fail
+                            r.truncate(limit);
+                            *truncated = true;
+                            break;
+                        }
+                    }
+                    Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                    // Linux returns an error instead of EOF once the terminal is closed
+                    Err(_e) => break,
+                }
+            }
+            let (lock, condvar) = &*done;
+            *lock.lock().expect("lock") = true;
+            condvar.notify_all();
+        });
+
+        Ok((running, output))
+    }
+
+    /// Core part to run/pipe a command
+    ///
+    /// An owned command is moved into the result instead of cloned, e.g. `shell.core(command)`
+    /// instead of `shell.core(&command)`.
+    pub fn core<'a>(&self, command: impl Into<Cow<'a, Command>>) -> Result<Command> {
+        self.core_until(&mut command.into(), None, None)
+    }
+
+    /// Core part to run/pipe a command with an optional deadline
+    ///
+    /// An owned command is moved into the result instead of cloned; see [`Shell::run_owned`].
+    fn core_until(
+        &self,
+        command: &mut Cow<Command>,
+        deadline: Option<Instant>,
+        index: Option<usize>,
+    ) -> Result<Command> {
+        self.core_forward(command, deadline, index, None)
+    }
+
+    /// Run a single command like [`Shell::core_until`] but pass the output that would go to the
+    /// terminal to `sink` as it arrives if set
+    fn core_forward(
+        &self,
+        command: &mut Cow<Command>,
+        deadline: Option<Instant>,
+        index: Option<usize>,
+        mut sink: Option<&mut dyn FnMut(OutputChunk)>,
+    ) -> Result<Command> {
+        if let (Some(prefix), Some(i)) = (&self.output_files, index) {
+            return self.core_files(command, deadline, prefix, i, sink);
+        }
+
+        if let Some(before) = &self.before {
+            (before.0)(command);
+        }
+
+        let r = if command.is_empty() {
+            skipped(take_command(command))
+        } else {
+            #[cfg(all(unix, feature = "pty"))]
+            let mut r = if command.pty && matches!(command.stdout, Pipe::String(_)) {
+                let (running, output) = self.spawn_pty(command)?;
+                let mut r = running.wait_pty(output, deadline)?;
+                if let Pipe::String(_) = command.stderr {
+                    // Merged into stdout
+                    r.stderr = Pipe::String(Some(String::new()));
+                }
+                r
+            } else if let Some(sink) = &mut sink {
+                self.spawn_forwarding(command, deadline, *sink)?
+            } else {
+                self.spawn_moving(command, deadline, |running| running.wait_until(deadline))?
+            };
+
+            #[cfg(not(all(unix, feature = "pty")))]
+            let mut r = if let Some(sink) = &mut sink {
+                self.spawn_forwarding(command, deadline, *sink)?
+            } else {
+                self.spawn_moving(command, deadline, |running| running.wait_until(deadline))?
+            };
+            self.normalize(&mut r);
+
+            if self.print && self.decorate {
+                if let Pipe::String(Some(_s)) = &r.stdin {
+                    self.print_close();
+                }
+            }
+
+            r
+        };
+
+        if let Some(after) = &self.after {
+            (after.0)(&r);
+        }
+
+        Ok(r)
+    }
+
+    /// Spawn a command with the pipes that would go to the terminal forwarded to `sink` and wait
+    /// for it; see [`Shell::core_forward`]
+    fn spawn_forwarding(
+        &self,
+        command: &mut Cow<Command>,
+        deadline: Option<Instant>,
+        sink: &mut dyn FnMut(OutputChunk),
+    ) -> Result<Command> {
+        let forward = [command.stdout.is_inherit(), command.stderr.is_inherit()];
+        let originals = (command.stdout.clone(), command.stderr.clone());
+        if forward[0] {
+            command.to_mut().stdout = Pipe::string();
+        }
+        if forward[1] {
+            command.to_mut().stderr = Pipe::string();
+        }
+
+        let mut r = self.spawn_moving(command, deadline, |running| {
+            running.wait_forward(deadline, forward, sink)
+        })?;
+
+        if forward[0] {
+            r.stdout = originals.0;
+        }
+        if forward[1] {
+            r.stderr = originals.1;
+        }
+
+        Ok(r)
+    }
+
+    /// Run a command and write its stdout and stderr to files; see [`Shell::output_files`]
+    ///
+    /// Output that would go to the terminal is passed to `sink` if set once the command exits.
+    fn core_files(
+        &self,
+        command: &Command,
+        deadline: Option<Instant>,
+        prefix: &std::path::Path,
+        i: usize,
+        mut sink: Option<&mut dyn FnMut(OutputChunk)>,
+    ) -> Result<Command> {
+        let captured = Command {
+            stdout: Pipe::string(),
+            stderr: Pipe::string(),
+            ..command.clone()
+        };
+
+        let mut result = self.core_until(&mut Cow::Owned(captured), deadline, None)?;
+
+        if let Some(dir) = prefix.parent().filter(|x| !x.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)?;
+        }
+
+        for (ext, pipe, original) in [
+            ("out", &mut result.stdout, &command.stdout),
+            ("err", &mut result.stderr, &command.stderr),
+        ] {
+            let s = std::mem::replace(pipe, original.clone()).into_string();
+
+            let mut path = prefix.as_os_str().to_owned();
+            path.push(format!("-{i}.{ext}"));
+            std::fs::write(&path, &s)?;
+
+            if let Pipe::String(_) = original {
+                *pipe = Pipe::String(Some(s));
+            } else if let (true, Some(sink)) = (original.is_inherit() && !s.is_empty(), &mut sink) {
+                sink(if ext == "out" {
+                    OutputChunk::Stdout(s.into_bytes())
+                } else {
+                    OutputChunk::Stderr(s.into_bytes())
+                });
+            } else if original.is_inherit() && ext == "out" {
+                std::io::stdout().write_all(s.as_bytes())?;
+            } else if original.is_inherit() {
+                std::io::stderr().write_all(s.as_bytes())?;
+            }
+        }
+
+        Ok(result)
+    }
+
+    /// True if a command runs via a shell; see [`Shell::shell`], [`Shell::shell_argv`], and
+    /// [`Command::shell`]
+    fn uses_shell(&self, command: &Command) -> bool {
+        command.argv.is_none()
+            && match &command.shell {
+                Some(shell) => shell.is_some(),
+                None => self.shell_argv.is_some() || self.shell.is_some(),
+            }
+    }
+
+    /// Prepare the command; warns if the shell is disabled but the command uses shell features
+    fn prepare(&self, command: &Command) -> Result<(String, Vec<String>)> {
+        let expanded = if command.expand_env.unwrap_or(self.expand_env) {
+            // Variables are expanded without a shell so only warn about other features
+            expand_vars(&command.command, |_name| Some(String::new()), false)
+                .unwrap_or_else(|_e| command.command.clone())
+        } else {
+            command.command.clone()
+        };
+
+        if !self.uses_shell(command) && self.print && uses_shell_features(&expanded) {
+            let warning = format!(
+                "WARNING: The shell is disabled but {:?} uses shell features!",
+                command.command,
+            );
+            anstream::AutoStream::new(std::io::stderr().lock(), self.color.choice())
+                .write_fmt(format_args!("{}\n", warning.style(self.error_style)))
+                .expect("write");
+        }
+
+        let (prog, args) = self.resolve(command)?;
+
+        if self.verbose && self.print {
+            let argv = std::iter::once(&prog).chain(&args).collect::<Vec<_>>();
+            self.emitln(format_args!(
+                "{}",
+                format!("{argv:?}").style(Style::new().dimmed())
+            ));
+        }
+
+        Ok((prog, args))
+    }
+
+    /**
+    Resolve the program and arguments that would be executed for a command without running it
+
+    Applies [`Command::argv`], the shell (see [`Command::shell`] and [`Shell::shell`]), variable
+    expansion (see [`Shell::expand_env`]), and shlex splitting exactly as [`Shell::run`] does, which is useful to audit or log the real exec call.
+
+    ```
+    use sprint::*;
+
+    let shell = Shell {
+        shell: Some(String::from("bash -c")),
+        ..Default::default()
+    };
+
+    assert_eq!(
+        shell.resolve(&Command::new("echo $HOME")).unwrap(),
+        (String::from("bash"), vec![String::from("-c"), String::from("echo $HOME")]),
+    );
+
+    let shell = Shell {
+        shell: None,
+        ..Default::default()
+    };
+
+    assert_eq!(
+        shell.resolve(&Command::new("ls -l 'a b'")).unwrap(),
+        (String::from("ls"), vec![String::from("-l"), String::from("a b")]),
+    );
+    ```
+    */
+    pub fn resolve(&self, command: &Command) -> Result<(String, Vec<String>)> {
+        if let Some(argv) = &command.argv {
+            // Pre-split arguments; run command directly without shell or shlex
+            let mut args = argv.clone();
+            if args.is_empty() {
+                return Err(anyhow!("Empty command!"));
+            }
+            let prog = args.remove(0);
+            Ok((prog, args))
+        } else if let (None, Some(argv)) = (&command.shell, &self.shell_argv) {
+            // Pre-split shell; used verbatim without shlex
+            let mut args = argv.clone();
+            if args.is_empty() {
+                return Err(anyhow!("Empty shell argv!"));
+            }
+            let prog = args.remove(0);
+            args.push(command.command.clone());
+            Ok((prog, args))
+        } else if let Some(s) = command.shell.as_ref().unwrap_or(&self.shell) {
+            let mut args = shlex::split(s)
+                .filter(|x| !x.is_empty())
+                .ok_or_else(|| anyhow!("Failed to parse shell spec: {s:?}!"))?;
+            let prog = args.remove(0);
+            args.push(command.command.clone());
+            Ok((prog, args))
+        } else {
+            // Shell disabled; run command directly
+            let mut args = if command.expand_env.unwrap_or(self.expand_env) {
+                split_vars(
+                    &command.command,
+                    |name| std::env::var(name).ok(),
+                    self.expand_env_strict,
+                )?
+            } else {
+                shlex::split(&command.command)
+                    .ok_or_else(|| anyhow!("Failed to parse command: {:?}!", command.command))?
+            };
+            if args.is_empty() {
+                return Err(anyhow!("Empty command!"));
+            }
+            let prog = args.remove(0);
+            Ok((prog, args))
+        }
+    }
+
+    /// Print sprint's own output to [`Shell::out`] if set, otherwise to stdout, or stderr if
+    /// [`Shell::decorations_to_stderr`] is set
+    ///
+    /// Colors are enabled or disabled per [`Shell::color`] without changing global state.
+    pub fn emit(&self, args: std::fmt::Arguments) {
+        let choice = self.color.choice();
+        if let Some(out) = &self.out {
+            let mut out = out.0.lock().expect("lock");
+            anstream::AutoStream::new(&mut *out, choice).write_fmt(args)
+        } else if self.decorations_to_stderr {
+            anstream::AutoStream::new(std::io::stderr().lock(), choice).write_fmt(args)
+        } else {
+            anstream::AutoStream::new(std::io::stdout().lock(), choice).write_fmt(args)
+        }
+        .expect("write");
+    }
+
+    /// Print sprint's own output and a newline; see [`Shell::emit`]
+    pub fn emitln(&self, args: std::fmt::Arguments) {
+        self.emit(format_args!("{args}\n"));
+    }
+
+    /**
+    Display a file path as a clickable OSC 8 terminal hyperlink if [`Shell::hyperlinks`] is set
+
+    The path is displayed as is if hyperlinks are disabled or [`Shell::color`] is
+    [`ColorOverride::Never`]; [`Shell::emit`] also strips the hyperlink if colors are disabled.
+
+    ```
+    use sprint::*;
+
+    let shell = Shell {
+        hyperlinks: true,
+        ..Default::default()
+    };
+
+    assert_eq!(
+        shell.hyperlink(std::path::Path::new("/tmp/a b.txt")),
+        "\x1b]8;;file:///tmp/a%20b.txt\x1b\\/tmp/a b.txt\x1b]8;;\x1b\\",
+    );
+    ```
+    */
+    pub fn hyperlink(&self, path: &std::path::Path) -> String {
+        let text = path.display().to_string();
+        if !self.hyperlinks || self.color == ColorOverride::Never {
+            return text;
+        }
+
+        let Ok(absolute) = std::path::absolute(path) else {
+            return text;
+        };
+
+        // Percent-encode everything except unreserved characters and path separators
+        let mut url = String::from("file://");
+        for b in absolute.to_string_lossy().bytes() {
+            if b.is_ascii_alphanumeric() || b"-._~/".contains(&b) {
+                url.push(b as char);
+            } else {
+                url.push_str(&format!("%{b:02X}"));
+            }
+        }
+
+        format!("\x1b]8;;{url}\x1b\\{text}\x1b]8;;\x1b\\")
+    }
+
+    /// Flush sprint's own output
+    fn flush(&self) {
+        if let Some(out) = &self.out {
+            out.0.lock().expect("lock").flush().expect("flush");
+        } else if self.decorations_to_stderr {
+            std::io::stderr().flush().expect("flush");
+        } else {
+            std::io::stdout().flush().expect("flush");
+        }
+    }
+
+    /// Print the fence followed by the given number of newlines; see also [`Shell::open_fence`]
+    /// and [`Shell::close_fence`]
+    pub fn print_fence(&self, newlines: usize) {
+        self.emit(format_args!(
+            "{}{}",
+            styled(&self.fence, self.fence_style),
+            "\n".repeat(newlines),
+        ));
+    }
+
+    /// Print the opening fence without a newline so the info can follow it
+    ///
+    /// Unlike [`Shell::print_open`], bypasses the [`Shell::renderer`].
+    pub fn open_fence(&self) {
+        self.print_fence(0);
+    }
+
+    /// Print the closing fence and a blank line
+    ///
+    /// Unlike [`Shell::print_close`], bypasses the [`Shell::renderer`].
+    pub fn close_fence(&self) {
+        self.print_fence(2);
+    }
+
+    /// Print the start of a block with the given info via the [`Shell::renderer`]
+    pub fn print_open(&self, info: &str) {
+        self.emit(format_args!("{}", self.active_renderer().open(self, info)));
+    }
+
+    /// Print the end of a block via the [`Shell::renderer`]
+    pub fn print_close(&self) {
+        self.emit(format_args!("{}", self.active_renderer().close(self)));
+    }
+
+    /// Print the interactive prompt
+    pub fn interactive_prompt(&self, previous: bool) {
+        self.interactive_prompt_with(previous, &self.prompt);
+    }
+
+    /// Print the interactive prompt with a custom prompt string
+    pub fn interactive_prompt_with(&self, previous: bool, prompt: &str) {
+        self.interactive_prompt_status(previous, "", prompt);
+    }
+
+    /**
+    Print the interactive prompt after a command with its exit code if it failed
+
+    ```
+    use sprint::*;
+
+    let shell = Shell {
+        color: ColorOverride::Never,
+        ..Default::default()
+    };
+
+    let result = shell.core(Command::new("exit 2")).unwrap();
+
+    shell.interactive_prompt_result(&result); // Prints "```\n\n```text\n[2] $ "
+    ```
+    */
+    pub fn interactive_prompt_result(&self, result: &Command) {
+        let status = if result.succeeded() {
+            String::new()
+        } else if let Some(code) = result.code {
+            format!("[{code}] ")
+        } else {
+            String::from("[killed] ")
+        };
+        self.interactive_prompt_status(true, &status, &self.prompt);
+    }
+
+    /// Print the interactive prompt with a status in the error style before the prompt
+    fn interactive_prompt_status(&self, previous: bool, status: &str, prompt: &str) {
+        if previous {
+            self.print_close();
+        }
+
+        self.print_open(&self.info);
+        self.emit(format_args!("{}", styled(status, self.error_style)));
+        self.emit(format_args!("{}", styled(prompt, self.prompt_style)));
+
+        // Set the command style
+        self.emit(format_args!(
+            "{}",
+            Prefix {
+                style: self.command_style
+            }
+        ));
+        self.flush();
+    }
+
+    /// Clear the command style
+    pub fn interactive_prompt_reset(&self) {
+        self.emit(format_args!(
+            "{}",
+            Suffix {
+                style: self.command_style
+            }
+        ));
+        self.flush();
+    }
+
+    /// Simpler interface to run command(s)
+    pub fn run_str(&self, commands: &[&str]) -> Vec<Command> {
+        self.run(&commands.iter().map(|x| Command::new(x)).collect::<Vec<_>>())
+    }
+
+    /**
+    Run command(s) like [`Shell::run_str`] but return an error without running any of them if one
+    cannot be resolved (see [`Shell::resolve`]), e.g. it has unbalanced quotes, or its program is
+    not found
+
+    Commands that run but fail are reported in the results as with [`Shell::run`].
+
+    ```
+    use sprint::*;
+
+    let shell = Shell {
+        shell: None,
+        print: false,
+        ..Default::default()
+    };
+
+    assert_eq!(shell.try_run_str(&["echo hello", "false"]).unwrap().len(), 2);
+
+    let e = shell.try_run_str(&["echo hello", "echo 'unbalanced"]).unwrap_err();
+    assert_eq!(e.to_string(), "Failed to parse command: \"echo 'unbalanced\"!");
+
+    let e = shell.try_run_str(&["not-a-real-command"]).unwrap_err();
+    assert_eq!(e.to_string(), "Program `not-a-real-command` not found!");
+    ```
+    */
+    pub fn try_run_str(&self, commands: &[&str]) -> Result<Vec<Command>> {
+        let commands = commands.iter().map(|x| Command::new(x)).collect::<Vec<_>>();
+
+        for command in commands.iter().filter(|x| !x.is_empty()) {
+            let (prog, _args) = self.resolve(command)?;
+            if self.which(&prog).is_none() {
+                let e = std::io::Error::from(std::io::ErrorKind::NotFound);
+                return Err(self.spawn_error(command, &prog, e));
+            }
+        }
+
+        Ok(self.run(&commands))
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl Shell {
+    /**
+    Run command(s) asynchronously via [`tokio::process`]; requires the `tokio` feature
+
+    Runs commands like [`Shell::run`] without blocking a thread while they run.
+    If [`Shell::sync`] is not set, all commands are started and then awaited, so they run
+    concurrently.
+
+    Not supported, i.e. ignored:
+
+    * [`Shell::batch_timeout`]
+    * [`Shell::out`] and [`Shell::output_prefix`] for the command output, which goes to the
+      terminal
+    * [`Shell::output_files`]
+    * [`Shell::fence_empty`]
+    * [`Shell::progress`]
+    * [`Command::capture_limit`] and [`Command::tail`]
+    * [`Command::pty`]
+
+    ```
+    use sprint::*;
+
+    # tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap().block_on(async {
+    let results = Shell::default()
+        .run_async(&[Command::new("echo a"), Command::new("echo b")])
+        .await;
+
+    assert_eq!(results.len(), 2);
+    assert!(results_ok(&results));
+    # });
+    ```
+    */
+    pub async fn run_async(&self, commands: &[Command]) -> Vec<Command> {
+        if !self.sync {
+            // Start every command, then wait for each
+            let mut started = vec![];
+            for command in commands {
+                started.push(self.start_tokio(command).await);
+            }
+
+            let mut r = vec![];
+            let mut errors = vec![];
+            for (command, child) in commands.iter().zip(started) {
+                let result = match child {
+                    Ok(child) => self.finish_tokio(command, child).await,
+                    Err(e) => Err(e),
+                };
+                r.push(result.unwrap_or_else(|e| {
+                    errors.push(e.to_string());
+                    command.clone()
+                }));
+            }
+
+            if self.print {
+                self.print_errors(&errors, &r);
+            }
+
+            return r;
+        }
+
+        // Info of the current block
+        let mut info: Option<String> = None;
+
+        let mut r = vec![];
+        let mut errors = vec![];
+
+        for command in commands {
+            self.print_block(&mut info, command);
+
+            let result = match self.start_tokio(command).await {
+                Ok(child) => self.finish_tokio(command, child).await,
+                Err(e) => Err(e),
+            };
+
+            match result {
+                Ok(result) => {
+                    errors.extend(self.error(&result));
+                    r.push(result);
+                }
+                Err(e) => {
+                    errors.push(e.to_string());
+                    r.push(command.clone());
+                }
+            }
+
+            if self.stop(&errors, None) {
+                break;
+            }
+        }
+
+        self.print_end(info, &errors, &r);
+
+        r
+    }
+
+    /// Print and start a command; returns `None` if there is nothing to run
+    async fn start_tokio(&self, command: &Command) -> Result<Option<tokio::process::Child>> {
+        if self.print && (self.decorate || self.dry_run) {
+            self.emit(format_args!("{}", self.format_command(command)));
+        }
+
+        if self.dry_run {
+            return Ok(None);
+        }
+
+        if let Some(before) = &self.before {
+            (before.0)(command);
+        }
+
+        if command.is_empty() {
+            return Ok(None);
+        }
+
+        let (prog, args) = self.prepare(command)?;
+
+        let mut cmd = tokio::process::Command::new(&prog);
+        cmd.args(&args);
+
+        cmd.stdin(command.stdin.stdio());
+        cmd.stdout(command.stdout.stdio());
+        cmd.stderr(command.stderr.stdio());
+
+        if self.env_clear {
+            cmd.env_clear();
+            cmd.envs(self.kept_env());
+        }
+
+        if self.process_group {
+            // Run the command in its own process group so the whole group can be killed
+            #[cfg(unix)]
+            cmd.process_group(0);
+
+            #[cfg(windows)]
+            cmd.creation_flags(0x00000200); // CREATE_NEW_PROCESS_GROUP
+        }
+
+        #[cfg(unix)]
+        if let Some(f) = pre_exec(command) {
+            unsafe {
+                cmd.pre_exec(f);
+            }
+        }
+
+        self.print_stdin(command);
+
+        let child = cmd
+            .spawn()
+            .map_err(|e| self.spawn_error(command, &prog, e))?;
+
+        Ok(Some(child))
+    }
+
+    /// Wait for a command started by [`Shell::start_tokio`] and return the result
+    async fn finish_tokio(
+        &self,
+        command: &Command,
+        child: Option<tokio::process::Child>,
+    ) -> Result<Command> {
+        if self.dry_run {
+            return Ok(dry_run(command.clone()));
+        }
+
+        let r = if let Some(mut child) = child {
+            let mut r = Command {
+                pid: child.id(),
+                ..command.clone()
+            };
+
+            // Write the stdin while the output is read; a write error only means that the command
+            // stopped reading
+            let stdin = child.stdin.take();
+            let write = async {
+                if let (Some(mut stdin), Pipe::String(Some(s))) = (stdin, &command.stdin) {
+                    tokio::io::AsyncWriteExt::write_all(&mut stdin, s.as_bytes())
+                        .await
+                        .ok();
+                }
+            };
+            let (output, ()) = tokio::join!(child.wait_with_output(), write);
+            let output = output?;
+            r.code = output.status.code();
+            r.signal = exit_signal(output.status);
+
+            for (pipe, output) in [
+                (&mut r.stdout, output.stdout),
+                (&mut r.stderr, output.stderr),
+            ] {
+                if matches!(pipe, Pipe::String(_)) {
+                    *pipe = Pipe::String(Some(String::from_utf8_lossy(&output).into_owned()));
+                }
+            }
+            self.normalize(&mut r);
+
+            if self.print && self.decorate {
+                if let Pipe::String(Some(_s)) = &command.stdin {
+                    self.print_close();
+                }
+            }
+
+            r
+        } else {
+            skipped(command.clone())
+        };
+
+        if let Some(after) = &self.after {
+            (after.0)(&r);
+        }
+
+        Ok(r)
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Command {
+    pub command: String,
+    pub argv: Option<Vec<String>>,
+    /// Override [`Shell::shell`] for this command: `None` uses the shell's setting, `Some(None)`
+    /// runs the command directly, and `Some(Some(s))` runs it via `s`
+    pub shell: Option<Option<String>>,
+    /// Override [`Shell::expand_env`] for this command
+    pub expand_env: Option<bool>,
+    /// Niceness to run the command at, e.g. `10` for a lower priority; Unix only, ignored elsewhere
+    pub nice: Option<i32>,
+    /// File mode creation mask to run the command with, e.g. `0o077`; Unix only, ignored elsewhere
+    pub umask: Option<u32>,
+    pub info: Option<String>,
+    /// Where the command reads its stdin from:
+    ///
+    /// * [`Pipe::Null`] (default): reads EOF immediately so the command cannot hang waiting for input
+    /// * [`Pipe::Inherit`]: reads the parent's stdin, e.g. for `cat` with no arguments or prompts
+    /// * [`Pipe::String`]: `Some(s)` writes `s` then closes stdin; `None` keeps stdin open for
+    ///   [`RunningCommand::write_stdin`]
+    pub stdin: Pipe,
+    pub codes: Vec<i32>,
+    pub allow_failure: bool,
+    pub stdout: Pipe,
+    pub stderr: Pipe,
+    pub code: Option<i32>,
+    /// Signal that killed the command, e.g. `11` for `SIGSEGV`; Unix only, `None` elsewhere
+    pub signal: Option<i32>,
+    /// Process ID of the child process; set once the command is spawned
+    pub pid: Option<u32>,
+    /// Override [`Shell::normalize_newlines`] for this command
+    pub normalize_newlines: Option<bool>,
+    /// Maximum number of bytes to capture from each [`Pipe::String`] stdout or stderr; the
+    /// command is killed if it produces more (not supported by `Shell::run_async`)
+    pub capture_limit: Option<usize>,
+    /// Keep only the last lines of each [`Pipe::String`] stdout or stderr, e.g. for diagnosing a
+    /// failed verbose command with bounded memory; overrides [`Command::capture_limit`] (not
+    /// supported by `Shell::run_async` or with [`Command::pty`])
+    pub tail: Option<usize>,
+    /// True if the captured output was truncated at [`Command::capture_limit`]
+    pub truncated: bool,
+    /// True if the command was not run because [`Shell::dry_run`] is set; counts as a success
+    pub dry_run: bool,
+    /// Attach a [`Pipe::String`] stdout to a pseudo-terminal so the command behaves as in a
+    /// terminal, e.g. keeps its colors; a [`Pipe::String`] stderr is merged into stdout.
+    /// With a [`Shell::batch_timeout`], the output is read until the deadline at most, e.g. if a
+    /// background process holds the terminal open.
+    /// Requires the `pty` feature, which is Unix only; ignored without it or by
+    /// `Shell::run_async`.
+    pub pty: bool,
+}
+
+impl From<&str> for Command {
+    fn from(command: &str) -> Command {
+        Command::new(command)
+    }
+}
+
+impl From<String> for Command {
+    fn from(command: String) -> Command {
+        Command::new(&command)
+    }
+}
+
+impl<'a> From<&'a Command> for Cow<'a, Command> {
+    fn from(command: &'a Command) -> Cow<'a, Command> {
+        Cow::Borrowed(command)
+    }
+}
+
+impl From<Command> for Cow<'_, Command> {
+    fn from(command: Command) -> Self {
+        Cow::Owned(command)
+    }
+}
+
+impl Default for Command {
+    fn default() -> Command {
+        Command {
+            command: Default::default(),
+            argv: None,
+            shell: None,
+            expand_env: None,
+            nice: None,
+            umask: None,
+            info: None,
+            stdin: Pipe::Null,
+            codes: vec![0],
+            allow_failure: false,
+            stdout: Pipe::Stdout,
+            stderr: Pipe::Stderr,
+            code: Default::default(),
+            signal: None,
+            pid: None,
+            normalize_newlines: None,
+            capture_limit: None,
+            tail: None,
+            truncated: false,
+            dry_run: false,
+            pty: false,
+        }
+    }
+}
+
+impl Command {
+    pub fn new(command: &str) -> Command {
+        Command {
+            command: command.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// True if there is nothing to run; i.e. the command is empty or only whitespace
+    pub fn is_empty(&self) -> bool {
+        match &self.argv {
+            Some(argv) => argv.is_empty(),
+            None => self.command.trim().is_empty(),
+        }
+    }
+
+    /**
+    Accept any exit code in a range as a success; see [`Command::codes`]
+
+    ```
+    use sprint::*;
+
+    let command = Command::new("exit 3").codes_range(0..=4);
+
+    assert_eq!(command.codes, vec![0, 1, 2, 3, 4]);
+    assert!(Shell::default().run1(&command).unwrap().succeeded());
+    ```
+    */
+    pub fn codes_range(self, codes: std::ops::RangeInclusive<i32>) -> Command {
+        Command {
+            codes: codes.collect(),
+            ..self
+        }
+    }
+
+    /// True if the command's result is a success; i.e. it exited with one of [`Command::codes`],
+    /// [`Command::allow_failure`] is set, or it was not run due to [`Shell::dry_run`]
+    ///
+    /// A command that was killed by a signal, or has not been run otherwise, has no code and did
+    /// not succeed.
+    pub fn succeeded(&self) -> bool {
+        self.allow_failure || self.dry_run || self.is_success(self.code)
+    }
+
+    /**
+    True if an exit code is acceptable; i.e. it is one of [`Command::codes`]
+
+    `None`, i.e. killed by a signal, is never acceptable.
+    Unlike [`Command::succeeded`], [`Command::allow_failure`] is not considered.
+
+    ```
+    use sprint::*;
+
+    let command = Command::new("grep a file").codes_range(0..=1);
+
+    assert!(command.is_success(Some(1)));
+    assert!(!command.is_success(Some(2)));
+    assert!(!command.is_success(None));
+    ```
+    */
+    pub fn is_success(&self, code: Option<i32>) -> bool {
+        code.is_some_and(|code| self.codes.contains(&code))
+    }
+
+    /**
+    Create a command from pre-split arguments
+
+    The arguments are passed directly to the program without a shell or shlex parsing.
+
+    ```
+    use sprint::*;
+
+    let command = Command::exec(vec!["git", "commit", "-m", "a b c"]);
+
+    assert_eq!(command.command, "git commit -m 'a b c'");
+    ```
+    */
+    pub fn exec(argv: impl IntoIterator<Item = impl Into<String>>) -> Command {
+        let argv = argv.into_iter().map(Into::into).collect::<Vec<String>>();
+        Command {
+            command: shlex::try_join(argv.iter().map(|x| x.as_str()))
+                .unwrap_or_else(|_| argv.join(" ")),
+            argv: Some(argv),
+            ..Default::default()
+        }
+    }
+}
+
+/**
+True if every result of [`Shell::run`] succeeded; see [`Command::succeeded`]
+
+```
+use sprint::*;
+
+let shell = Shell::default();
+
+assert!(results_ok(&shell.run(&[Command::new("true"), Command::new("true")])));
+assert!(!results_ok(&shell.run(&[Command::new("true"), Command::new("false")])));
+```
+*/
+pub fn results_ok(results: &[Command]) -> bool {
+    results.iter().all(Command::succeeded)
+}
+
+/**
+Index the results of [`Shell::run`] by command string
+
+If a command appears more than once, the last result is kept.
+
+```
+use sprint::*;
+
+let shell = Shell {
+    print: false,
+    ..Default::default()
+};
+
+let results = results_map(shell.run(&["true".into(), "exit 3".into()]));
+
+assert_eq!(results["exit 3"].code, Some(3));
+assert!(results.get("cargo build").is_none());
+```
+*/
+pub fn results_map(results: Vec<Command>) -> BTreeMap<String, Command> {
+    results
+        .into_iter()
+        .map(|x| (x.command.clone(), x))
+        .collect()
+}
+
+/**
+Number of commands in a batch that succeeded and failed; see [`Shell::keep_going`]
+
+```
+use sprint::*;
+
+let shell = Shell {
+    keep_going: true,
+    ..Default::default()
+};
+
+let results = shell.run(&["true".into(), "false".into(), "true".into()]);
+let summary = Summary::new(&results);
+
+assert_eq!(summary, Summary { succeeded: 2, failed: 1 });
+assert_eq!(summary.to_string(), "2 succeeded, 1 failed");
+```
+*/
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Summary {
+    pub succeeded: usize,
+    pub failed: usize,
+}
+
+impl Summary {
+    /// Count the results that succeeded and failed; see [`Command::succeeded`]
+    pub fn new(results: &[Command]) -> Summary {
+        let succeeded = results.iter().filter(|x| x.succeeded()).count();
+        Summary {
+            succeeded,
+            failed: results.len() - succeeded,
+        }
+    }
+}
+
+impl std::fmt::Display for Summary {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} succeeded, {} failed", self.succeeded, self.failed)
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+
+/// Target of a task runner; see [`parse_targets`] and [`Shell::run_target`]
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Target {
+    /// Targets to run before this one
+    pub dependencies: Vec<String>,
+    /// List items in backticks that don't name a target, e.g. files
+    pub files: Vec<String>,
+    pub commands: Vec<Command>,
+}
+
+impl From<Vec<Command>> for Target {
+    fn from(commands: Vec<Command>) -> Target {
+        Target {
+            dependencies: vec![],
+            files: vec![],
+            commands,
+        }
+    }
+}
+
+/**
+Parse a Markdown document into a map of targets; see [`Shell::run_target`]
 
-                r.push(result);
+Each `# target` heading starts a target, each `* dependency` list item names a target to run
+before it, and each line in its shell code blocks is a command.
+Code blocks with an info string other than `sh`, `bash`, `zsh`, `shell`, or `console` (e.g.
+` ```toml `) are skipped.
+Backticks around headings and list items are removed; list items in backticks are dependencies
+if they name a target and [`Target::files`] otherwise, so files can be listed as in `Makefile.md`.
 
-                if error.is_some() {
-                    break;
-                }
-            }
+```
+use sprint::*;
 
-            if self.print {
-                self.print_fence(2);
+let targets = parse_targets(
+    "# build\n\n```\ncargo build\ncargo doc\n```\n\n# clean\n\n```sh\ncargo clean\n```\n\n\
+     ```toml\n[package]\n```\n",
+);
 
-                if let Some(error) = error {
-                    println!("{}\n", error.style(self.error_style));
+assert_eq!(targets.keys().collect::<Vec<_>>(), ["build", "clean"]);
+assert_eq!(
+    targets["build"].commands,
+    [Command::new("cargo build"), Command::new("cargo doc")],
+);
+assert_eq!(targets["clean"].commands, [Command::new("cargo clean")]);
+
+let targets = parse_targets("# all\n\n* `build`\n* `Cargo.toml`\n\n# `build`\n");
+
+assert_eq!(targets["all"].dependencies, ["build"]);
+assert_eq!(targets["all"].files, ["Cargo.toml"]);
+```
+*/
+pub fn parse_targets(markdown: &str) -> BTreeMap<String, Target> {
+    let mut r = BTreeMap::new();
+    let mut target: Option<&mut Target> = None;
+    // Whether the current fenced code block, if any, contains shell commands
+    let mut fence: Option<bool> = None;
+
+    for line in markdown.lines() {
+        if let Some(info) = line.strip_prefix("```") {
+            fence = match fence {
+                Some(_shell) => None,
+                None => Some(matches!(
+                    info.split_whitespace().next().unwrap_or(""),
+                    "" | "sh" | "bash" | "zsh" | "shell" | "console",
+                )),
+            };
+        } else if let Some(name) = line.strip_prefix("# ").filter(|_x| fence.is_none()) {
+            target = Some(r.entry(unquote(name).to_string()).or_default());
+        } else if let Some(target) = &mut target {
+            if let Some(shell) = fence {
+                if shell && !line.trim().is_empty() {
+                    target.commands.push(Command::new(line));
+                }
+            } else if let Some(dependency) = line.strip_prefix("* ") {
+                let dependency = dependency.trim();
+                target.dependencies.push(unquote(dependency).to_string());
+                if dependency.starts_with('`') {
+                    target.files.push(unquote(dependency).to_string());
                 }
             }
-
-            r
-        } else {
-            commands
-                .par_iter()
-                .map(|command| self.run1(command))
-                .collect()
         }
     }
 
-    /// Run a single command
-    pub fn run1(&self, command: &Command) -> Command {
-        if self.print {
-            if !self.dry_run {
-                print!("{}", self.prompt.style(self.prompt_style));
-            }
+    // Backticked list items are dependencies only if they name a target
+    let names = r.keys().cloned().collect::<BTreeSet<_>>();
+    for target in r.values_mut() {
+        let files = std::mem::take(&mut target.files);
+        target
+            .dependencies
+            .retain(|x| names.contains(x) || !files.contains(x));
+        target.files = files.into_iter().filter(|x| !names.contains(x)).collect();
+    }
 
-            println!(
-                "{}",
-                command
-                    .command
-                    .replace(" && ", " \\\n&& ")
-                    .replace(" || ", " \\\n|| ")
-                    .replace("; ", "; \\\n")
-                    .style(self.command_style),
-            );
-        }
+    r
+}
 
-        if self.dry_run {
-            return command.clone();
-        }
+/// Remove surrounding whitespace and backticks; see [`parse_targets`]
+fn unquote(s: &str) -> &str {
+    let s = s.trim();
+    s.strip_prefix('`')
+        .and_then(|x| x.strip_suffix('`'))
+        .unwrap_or(s)
+}
 
-        self.core(command)
+/// Add a target to the run order after its dependencies; see [`Shell::run_target`]
+fn resolve_target<'a>(
+    targets: &'a BTreeMap<String, Target>,
+    name: &'a str,
+    stack: &mut Vec<&'a str>,
+    order: &mut Vec<&'a str>,
+) -> Result<()> {
+    if order.contains(&name) {
+        return Ok(());
     }
 
-    /// Pipe a single command
-    pub fn pipe1(&self, command: &str) -> String {
-        let command = Command {
-            command: command.to_string(),
-            stdout: Pipe::string(),
-            ..Default::default()
-        };
-
-        let result = self.core(&command);
+    if let Some(i) = stack.iter().position(|x| *x == name) {
+        let cycle = stack[i..]
+            .iter()
+            .chain([&name])
+            .map(|x| format!("`{x}`"))
+            .collect::<Vec<_>>()
+            .join(" -> ");
+        return Err(anyhow!("Dependency cycle: {cycle}!"));
+    }
 
-        if let Pipe::String(Some(stdout)) = &result.stdout {
-            stdout.to_string()
+    let target = targets.get(name).ok_or_else(|| {
+        let available = if targets.is_empty() {
+            String::from("none")
         } else {
-            String::new()
-        }
+            targets
+                .keys()
+                .map(|x| format!("`{x}`"))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        anyhow!("Target `{name}` not found! Available targets: {available}.")
+    })?;
+
+    stack.push(name);
+    for dependency in &target.dependencies {
+        resolve_target(targets, dependency, stack, order)?;
     }
+    stack.pop();
 
-    /// Run a command in a child process
-    pub fn run1_async(&self, command: &Command) -> std::process::Child {
-        let (prog, args) = self.prepare(&command.command);
+    order.push(name);
+    Ok(())
+}
 
-        let mut cmd = std::process::Command::new(prog);
-        cmd.args(&args);
+//--------------------------------------------------------------------------------------------------
 
-        if matches!(command.stdin, Pipe::String(_)) {
-            cmd.stdin(std::process::Stdio::piped());
-        }
+/// Captured output of a command; see [`Shell::capture`]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Output {
+    pub code: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+    pub pid: Option<u32>,
+}
 
-        if matches!(command.stdout, Pipe::String(_) | Pipe::Null) {
-            cmd.stdout(std::process::Stdio::piped());
-        }
+//--------------------------------------------------------------------------------------------------
 
-        if matches!(command.stderr, Pipe::String(_) | Pipe::Null) {
-            cmd.stderr(std::process::Stdio::piped());
-        }
+/// State of output forwarded from running commands; see [`Shell::print_output`]
+#[derive(Debug, Default)]
+struct Forwarded {
+    /// Whether stdout and stderr ended in the middle of a line
+    mid_line: [bool; 2],
+    /// Incomplete UTF-8 sequence at the end of the last chunk of stdout and stderr
+    partial: [Vec<u8>; 2],
+}
 
-        if self.print {
-            if let Pipe::String(Some(s)) = &command.stdin {
-                self.print_fence(0);
-                println!("{}", command.command.style(self.info_style));
-                println!("{s}");
-                self.print_fence(2);
-                self.print_fence(0);
-                println!("{}", self.info.style(self.info_style));
+//--------------------------------------------------------------------------------------------------
+
+/// Chunk of a command's streamed output; see [`Shell::run_channel`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum OutputChunk {
+    Stdout(Vec<u8>),
+    Stderr(Vec<u8>),
+    Exit(Option<i32>),
+}
+
+/// Send chunks read from a stream until EOF or the receiver is dropped
+fn send_chunks(
+    mut reader: impl Read,
+    tx: &std::sync::mpsc::SyncSender<OutputChunk>,
+    chunk: fn(Vec<u8>) -> OutputChunk,
+) {
+    let mut buf = [0; 4096];
+    loop {
+        match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => {
+                if tx.send(chunk(buf[..n].to_vec())).is_err() {
+                    break;
+                }
             }
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(_e) => break,
         }
+    }
+}
 
-        let mut child = cmd.spawn().unwrap();
+//--------------------------------------------------------------------------------------------------
+
+/**
+Stdio overrides for spawning a command; see [`Shell::run1_async_with`]
+
+Each stream that is set replaces the one derived from the command's [`Pipe`], e.g. to share a file
+descriptor between commands or connect a command to a socket.
+
+```
+use {sprint::*, std::process::Stdio};
+
+let shell = Shell {
+    print: false,
+    ..Default::default()
+};
+
+let mut producer = shell
+    .run1_async_with(
+        &Command::new("echo hello"),
+        SpawnOptions {
+            stdout: Some(Stdio::piped()),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+
+let consumer = shell
+    .run1_async_with(
+        &Command::new("tr a-z A-Z"),
+        SpawnOptions {
+            stdin: Some(producer.stdout.take().unwrap().into()),
+            stdout: Some(Stdio::piped()),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+
+producer.wait().unwrap();
+let output = consumer.wait_with_output().unwrap();
+
+assert_eq!(String::from_utf8(output.stdout).unwrap(), "HELLO\n");
+```
+*/
+#[derive(Debug, Default)]
+pub struct SpawnOptions {
+    pub stdin: Option<std::process::Stdio>,
+    pub stdout: Option<std::process::Stdio>,
+    pub stderr: Option<std::process::Stdio>,
+}
+
+//--------------------------------------------------------------------------------------------------
+
+/**
+Command running in a child process
+
+Created via [`Shell::spawn`].
+*/
+#[derive(Debug)]
+pub struct RunningCommand {
+    pub command: Command,
+    pub child: std::process::Child,
+    pub stdin: Option<std::process::ChildStdin>,
+    pub stdout: Option<std::process::ChildStdout>,
+    pub stderr: Option<std::process::ChildStderr>,
+    pub process_group: bool,
+}
 
-        if let Pipe::String(Some(s)) = &command.stdin {
-            let mut stdin = child.stdin.take().unwrap();
-            stdin.write_all(s.as_bytes()).unwrap();
+impl RunningCommand {
+    /// Write to the command's stdin; requires a [`Pipe::String`] stdin
+    pub fn write_stdin(&mut self, data: &[u8]) -> Result<()> {
+        if let Some(stdin) = &mut self.stdin {
+            stdin.write_all(data)?;
+            Ok(())
+        } else {
+            Err(anyhow!(
+                "Command `{}` has no stdin pipe!",
+                self.command.command
+            ))
         }
+    }
 
-        child
+    /// Kill the command if it is still running
+    ///
+    /// Kills the command's whole process group on Unix if [`Shell::process_group`] was set.
+    pub fn kill(&mut self) -> Result<()> {
+        if let Ok(None) = self.child.try_wait() {
+            #[cfg(unix)]
+            if self.process_group {
+                // The process group ID is the child's PID
+                if unsafe { libc::killpg(self.child.id() as libc::pid_t, libc::SIGKILL) } != 0 {
+                    return Err(std::io::Error::last_os_error().into());
+                }
+                self.child.wait()?;
+                return Ok(());
+            }
+
+            self.child.kill()?;
+        }
+        Ok(())
     }
 
-    /// Core part to run/pipe a command
-    pub fn core(&self, command: &Command) -> Command {
-        let mut child = self.run1_async(command);
+    /// Close stdin, wait for the command to exit, and return the result
+    ///
+    /// Kills the command if its captured output exceeds [`Command::capture_limit`].
+    pub fn wait(mut self) -> Command {
+        drop(self.stdin.take());
 
-        let mut r = command.clone();
+        let mut r = std::mem::take(&mut self.command);
 
-        r.code = match child.wait() {
-            Ok(status) => status.code(),
-            Err(_e) => None,
+        // Read stderr in the background so the command cannot block on a full stderr pipe while
+        // stdout is read
+        let stderr = match (&r.stderr, self.stderr.take()) {
+            (Pipe::String(_), Some(pipe)) => Some(read_thread(
+                pipe,
+                r.capture_limit,
+                r.tail,
+                Default::default(),
+            )),
+            _ => None,
         };
 
-        if matches!(command.stdout, Pipe::String(_)) {
-            let mut stdout = String::new();
-            child.stdout.unwrap().read_to_string(&mut stdout).unwrap();
+        if matches!(r.stdout, Pipe::String(_)) {
+            let (stdout, truncated) =
+                read_limited(self.stdout.as_mut().unwrap(), r.capture_limit, r.tail);
             r.stdout = Pipe::String(Some(stdout));
+            if truncated {
+                r.truncated = true;
+                self.kill().ok();
+            }
         }
 
-        if matches!(command.stderr, Pipe::String(_)) {
-            let mut stderr = String::new();
-            child.stderr.unwrap().read_to_string(&mut stderr).unwrap();
+        if let Some(stderr) = stderr {
+            let (stderr, truncated) = stderr.join().expect("join");
             r.stderr = Pipe::String(Some(stderr));
+            if truncated {
+                r.truncated = true;
+                self.kill().ok();
+            }
         }
 
-        if self.print {
-            if let Pipe::String(Some(_s)) = &command.stdin {
-                self.print_fence(2);
-            }
+        if let Ok(status) = self.child.wait() {
+            r.code = status.code();
+            r.signal = exit_signal(status);
         }
 
         r
     }
 
-    /// Prepare the command
-    fn prepare(&self, command: &str) -> (String, Vec<String>) {
-        if let Some(s) = &self.shell {
-            let mut args = shlex::split(s).unwrap();
-            let prog = args.remove(0);
-            args.push(command.to_string());
-            (prog, args)
-        } else {
-            // Shell disabled; run command directly
-            let mut args = shlex::split(command).unwrap();
-            let prog = args.remove(0);
-            (prog, args)
+    /// Wait for the command like [`RunningCommand::wait`] but kill it at the deadline
+    fn wait_until(self, deadline: Option<Instant>) -> Result<Command> {
+        if deadline.is_none() {
+            return Ok(self.wait());
         }
+
+        self.wait_forward(deadline, [false; 2], &mut |_chunk| {})
     }
 
-    /// Print the fence
-    pub fn print_fence(&self, newlines: usize) {
-        print!(
-            "{}{}",
-            self.fence.style(self.fence_style),
-            "\n".repeat(newlines),
-        );
+    /// Wait for a command attached to a pseudo-terminal like [`RunningCommand::wait_until`] and
+    /// get its output; the command is killed if its output exceeds [`Command::capture_limit`]
+    #[cfg(all(unix, feature = "pty"))]
+    fn wait_pty(mut self, output: PtyOutput, deadline: Option<Instant>) -> Result<Command> {
+        drop(self.stdin.take());
+
+        let status = loop {
+            if output.truncated() {
+                self.kill().ok();
+            }
+            if let Some(status) = self.child.try_wait()? {
+                break status;
+            }
+            if deadline.is_some_and(|x| Instant::now() >= x) {
+                self.kill()?;
+                return Err(anyhow!(
+                    "Command `{}` killed at the deadline!",
+                    self.command.command
+                ));
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        };
+
+        let mut r = self.command;
+        let (stdout, truncated) = output.join(deadline);
+        r.stdout = Pipe::String(Some(stdout));
+        r.truncated = truncated;
+        r.code = status.code();
+        r.signal = exit_signal(status);
+
+        Ok(r)
     }
 
-    /// Print the interactive prompt
-    pub fn interactive_prompt(&self, previous: bool) {
-        if previous {
-            self.print_fence(2);
+    /// Wait for the command like [`RunningCommand::wait_until`] and pass the output of stdout and
+    /// stderr, per `forward`, to `sink` as it arrives instead of capturing it
+    fn wait_forward(
+        mut self,
+        deadline: Option<Instant>,
+        forward: [bool; 2],
+        sink: &mut dyn FnMut(OutputChunk),
+    ) -> Result<Command> {
+        drop(self.stdin.take());
+
+        // Read the pipes in the background so the command cannot block on a full pipe
+        let (limit, tail) = (self.command.capture_limit, self.command.tail);
+        let truncated = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let (tx, rx) = std::sync::mpsc::sync_channel(16);
+        let stdout = match self.stdout.take() {
+            Some(pipe) if forward[0] => {
+                let tx = tx.clone();
+                std::thread::spawn(move || send_chunks(pipe, &tx, OutputChunk::Stdout));
+                None
+            }
+            pipe => pipe.map(|x| read_thread(x, limit, tail, truncated.clone())),
+        };
+        let stderr = match self.stderr.take() {
+            Some(pipe) if forward[1] => {
+                let tx = tx.clone();
+                std::thread::spawn(move || send_chunks(pipe, &tx, OutputChunk::Stderr));
+                None
+            }
+            pipe => pipe.map(|x| read_thread(x, limit, tail, truncated.clone())),
+        };
+        drop(tx);
+
+        // Wait for the command to exit and the forwarded pipes to close
+        let mut status = None;
+        let mut forwarding = true;
+        while status.is_none() || forwarding {
+            if forwarding {
+                match rx.recv_timeout(Duration::from_millis(10)) {
+                    Ok(chunk) => sink(chunk),
+                    Err(std::sync::mpsc::RecvTimeoutError::Timeout) => {}
+                    Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => forwarding = false,
+                }
+            } else {
+                std::thread::sleep(Duration::from_millis(10));
+            }
+            if status.is_none() {
+                if truncated.load(std::sync::atomic::Ordering::SeqCst) {
+                    // Captured output exceeded the capture limit
+                    self.kill().ok();
+                }
+                status = self.child.try_wait()?;
+            }
+            if deadline.is_some_and(|x| Instant::now() >= x) {
+                if status.is_some() {
+                    // A background process of the command holds the pipes open
+                    break;
+                }
+                self.kill()?;
+                return Err(anyhow!(
+                    "Command `{}` killed at the deadline!",
+                    self.command.command
+                ));
+            }
         }
+        let status = status.unwrap();
 
-        self.print_fence(0);
-        println!("{}", self.info.style(self.info_style));
-        print!("{}", self.prompt.style(self.prompt_style));
+        let mut r = self.command;
+        for (pipe, output) in [(&mut r.stdout, stdout), (&mut r.stderr, stderr)] {
+            if let (Pipe::String(_), Some(output)) = (&pipe, output) {
+                let (output, truncated) = output.join().expect("join");
+                *pipe = Pipe::String(Some(output));
+                r.truncated |= truncated;
+            }
+        }
+        r.code = status.code();
+        r.signal = exit_signal(status);
 
-        // Set the command style
-        print_prefix(self.command_style);
-        std::io::stdout().flush().expect("flush");
+        Ok(r)
     }
+}
 
-    /// Clear the command style
-    pub fn interactive_prompt_reset(&self) {
-        print_suffix(self.command_style);
-        std::io::stdout().flush().expect("flush");
+/// Get the signal that killed a process; Unix only
+fn exit_signal(status: std::process::ExitStatus) -> Option<i32> {
+    #[cfg(unix)]
+    return std::os::unix::process::ExitStatusExt::signal(&status);
+
+    #[cfg(not(unix))]
+    {
+        let _ = status;
+        None
     }
+}
 
-    /// Simpler interface to run command(s)
-    pub fn run_str(&self, commands: &[&str]) -> Vec<Command> {
-        self.run(&commands.iter().map(|x| Command::new(x)).collect::<Vec<_>>())
+/// Get the name of a signal, e.g. `SIGSEGV`, or `signal N` if unknown
+fn signal_name(signal: i32) -> String {
+    #[cfg(unix)]
+    {
+        let name = match signal {
+            libc::SIGHUP => "SIGHUP",
+            libc::SIGINT => "SIGINT",
+            libc::SIGQUIT => "SIGQUIT",
+            libc::SIGILL => "SIGILL",
+            libc::SIGTRAP => "SIGTRAP",
+            libc::SIGABRT => "SIGABRT",
+            libc::SIGBUS => "SIGBUS",
+            libc::SIGFPE => "SIGFPE",
+            libc::SIGKILL => "SIGKILL",
+            libc::SIGUSR1 => "SIGUSR1",
+            libc::SIGSEGV => "SIGSEGV",
+            libc::SIGUSR2 => "SIGUSR2",
+            libc::SIGPIPE => "SIGPIPE",
+            libc::SIGALRM => "SIGALRM",
+            libc::SIGTERM => "SIGTERM",
+            _ => "",
+        };
+        if !name.is_empty() {
+            return name.to_string();
+        }
+    }
+
+    format!("signal {signal}")
+}
+
+/// Read a pipe to the end and keep only the last `n` lines
+fn read_tail(pipe: &mut impl Read, n: usize) -> String {
+    let mut reader = std::io::BufReader::new(pipe);
+    let mut lines = std::collections::VecDeque::new();
+    loop {
+        let mut line = vec![];
+        match std::io::BufRead::read_until(&mut reader, b'\n', &mut line) {
+            Ok(0) | Err(_) => break,
+            Ok(_) => {}
+        }
+        lines.push_back(line);
+        if lines.len() > n {
+            lines.pop_front();
+        }
+    }
+    String::from_utf8_lossy(&lines.into_iter().flatten().collect::<Vec<_>>()).into_owned()
+}
+
+/// Output of a command attached to a pseudo-terminal, read in the background; see
+/// [`Shell::spawn_pty`]
+#[cfg(all(unix, feature = "pty"))]
+#[derive(Default)]
+struct PtyOutput {
+    /// Output read so far and whether it was truncated at [`Command::capture_limit`]
+    buffer: Arc<Mutex<(Vec<u8>, bool)>>,
+    /// Set once the terminal is closed, i.e. the command and any background processes holding it
+    /// exited, or the output was truncated
+    done: Arc<(Mutex<bool>, std::sync::Condvar)>,
+}
+
+#[cfg(all(unix, feature = "pty"))]
+impl PtyOutput {
+    /// Whether the output was truncated at [`Command::capture_limit`]
+    fn truncated(&self) -> bool {
+        self.buffer.lock().expect("lock").1
+    }
+
+    /// Wait for the terminal to be closed, or only until the deadline if set, e.g. in case a
+    /// background process of the command holds it open; returns the output read so far and
+    /// whether it was truncated
+    fn join(self, deadline: Option<Instant>) -> (String, bool) {
+        let (lock, condvar) = &*self.done;
+        let mut done = lock.lock().expect("lock");
+        while !*done {
+            done = match deadline {
+                Some(deadline) => {
+                    let timeout = deadline.saturating_duration_since(Instant::now());
+                    if timeout.is_zero() {
+                        break;
+                    }
+                    condvar.wait_timeout(done, timeout).expect("wait").0
+                }
+                None => condvar.wait(done).expect("wait"),
+            };
+        }
+        drop(done);
+
+        let (r, truncated) = std::mem::take(&mut *self.buffer.lock().expect("lock"));
+        (String::from_utf8_lossy(&r).into_owned(), truncated)
+    }
+}
+
+/// Read a pipe in a thread up to the limit
+///
+/// Sets `truncated` as soon as the output is truncated so the command can be killed.
+fn read_thread(
+    mut pipe: impl Read + Send + 'static,
+    limit: Option<usize>,
+    tail: Option<usize>,
+    truncated: Arc<std::sync::atomic::AtomicBool>,
+) -> std::thread::JoinHandle<(String, bool)> {
+    std::thread::spawn(move || {
+        let r = read_limited(&mut pipe, limit, tail);
+        if r.1 {
+            truncated.store(true, std::sync::atomic::Ordering::SeqCst);
+        }
+        r
+    })
+}
+
+/// Write a string stdin in a thread so the command's output can be read meanwhile; returns the
+/// string once written
+///
+/// Write errors are ignored since they only mean that the command stopped reading, e.g. a broken
+/// pipe; its exit status tells whether it failed.
+fn write_thread(
+    mut stdin: impl Write + Send + 'static,
+    s: String,
+) -> std::thread::JoinHandle<String> {
+    std::thread::spawn(move || {
+        stdin.write_all(s.as_bytes()).ok();
+        // Close stdin so the command sees the end of its input
+        drop(stdin);
+        s
+    })
+}
+
+/// Read a pipe to the end or up to the limit; returns the output and whether it was truncated
+///
+/// Keeps only the last lines instead if there is a tail; see [`Command::tail`].
+fn read_limited(pipe: &mut impl Read, limit: Option<usize>, tail: Option<usize>) -> (String, bool) {
+    if let Some(n) = tail {
+        return (read_tail(pipe, n), false);
     }
+
+    let Some(limit) = limit else {
+        let mut r = vec![];
+        pipe.read_to_end(&mut r).unwrap();
+        return (String::from_utf8_lossy(&r).into_owned(), false);
+    };
+
+    let mut r = vec![];
+    pipe.take(limit as u64 + 1).read_to_end(&mut r).unwrap();
+    let truncated = r.len() > limit;
+    r.truncate(limit);
+    (String::from_utf8_lossy(&r).into_owned(), truncated)
 }
 
 //--------------------------------------------------------------------------------------------------
 
-#[derive(Clone, Debug, PartialEq, Eq)]
-pub struct Command {
-    pub command: String,
-    pub stdin: Pipe,
-    pub codes: Vec<i32>,
-    pub stdout: Pipe,
-    pub stderr: Pipe,
-    pub code: Option<i32>,
+/**
+Shell process reused across commands (experimental)
+
+Commands are written to the stdin of a single shell process and their output is read up to a
+sentinel, so shell state like the working directory, variables, and functions persists between
+commands and the shell's startup cost is only paid once.
+
+Each command's stdin is `/dev/null` and its output is captured, then stored or written per its
+[`Pipe`]s once it exits.
+A command with a syntax error, e.g. an unbalanced quote, fails with the shell's exit code for it.
+Process options like [`Command::argv`], [`Command::shell`], and [`Command::nice`] are ignored, as
+are [`Shell::batch_timeout`], [`Command::capture_limit`], [`Command::tail`], and a
+[`Pipe::String`] stdin.
+Unix only.
+
+```
+use sprint::*;
+
+let shell = Shell {
+    print: false,
+    ..Default::default()
+};
+
+let mut persistent = PersistentShell::new(&shell).unwrap();
+
+let results = persistent.run(&[
+    Command::new("cd / && export GREETING=hello"),
+    Command {
+        stdout: Pipe::string(),
+        ..Command::new("pwd; echo $GREETING")
+    },
+]);
+
+assert_eq!(results[1].stdout, Pipe::String(Some(String::from("/\nhello\n"))));
+```
+*/
+#[cfg(unix)]
+#[derive(Debug)]
+pub struct PersistentShell {
+    shell: Shell,
+    child: std::process::Child,
+    stdin: std::process::ChildStdin,
+    stdout: std::io::BufReader<std::process::ChildStdout>,
+    stderr: std::io::BufReader<std::process::ChildStderr>,
+    /// Marks the end of each command's output
+    sentinel: String,
 }
 
-impl Default for Command {
-    fn default() -> Command {
-        Command {
-            command: Default::default(),
-            stdin: Pipe::Null,
-            codes: vec![0],
-            stdout: Pipe::Stdout,
-            stderr: Pipe::Stderr,
-            code: Default::default(),
+#[cfg(unix)]
+impl PersistentShell {
+    /// Start a shell process per [`Shell::shell_argv`] or [`Shell::shell`]; a trailing `-c` is
+    /// dropped so the shell reads commands from stdin
+    pub fn new(shell: &Shell) -> Result<PersistentShell> {
+        let mut args = if let Some(argv) = &shell.shell_argv {
+            argv.clone()
+        } else if let Some(s) = &shell.shell {
+            shlex::split(s)
+                .filter(|x| !x.is_empty())
+                .ok_or_else(|| anyhow!("Failed to parse shell spec: {s:?}!"))?
+        } else {
+            return Err(anyhow!("A persistent shell requires a shell!"));
+        };
+        if args.last().is_some_and(|x| x == "-c") {
+            args.pop();
+        }
+        if args.is_empty() {
+            return Err(anyhow!("Empty shell argv!"));
+        }
+        let prog = args.remove(0);
+
+        let mut cmd = std::process::Command::new(&prog);
+        cmd.args(&args)
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped());
+
+        if shell.env_clear {
+            cmd.env_clear();
+            cmd.envs(shell.kept_env());
+        }
+
+        let mut child = cmd
+            .spawn()
+            .map_err(|e| shell.spawn_error(&Command::default(), &prog, e))?;
+
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+
+        Ok(PersistentShell {
+            shell: shell.clone(),
+            stdin: child.stdin.take().unwrap(),
+            stdout: std::io::BufReader::new(child.stdout.take().unwrap()),
+            stderr: std::io::BufReader::new(child.stderr.take().unwrap()),
+            sentinel: format!("__sprint_{}_{nanos}__", std::process::id()),
+            child,
+        })
+    }
+
+    /// Run command(s) one after the other like [`Shell::run`]
+    pub fn run(&mut self, commands: &[Command]) -> Vec<Command> {
+        // Info of the current block
+        let mut info: Option<String> = None;
+
+        let mut r = vec![];
+        let mut errors = vec![];
+
+        for command in commands {
+            self.shell.print_block(&mut info, command);
+            if self.shell.print && (self.shell.decorate || self.shell.dry_run) {
+                self.shell
+                    .emit(format_args!("{}", self.shell.format_command(command)));
+            }
+
+            match self.run1(command) {
+                Ok(result) => {
+                    errors.extend(self.shell.error(&result));
+                    r.push(result);
+                }
+                Err(e) => {
+                    errors.push(e.to_string());
+                    r.push(command.clone());
+                }
+            }
+
+            if self.shell.stop(&errors, None) {
+                break;
+            }
+        }
+
+        self.shell.print_end(info, &errors, &r);
+
+        r
+    }
+
+    /// Run a single command without printing it
+    ///
+    /// Returns an error if the shell exited, e.g. because the command ran `exit`.
+    pub fn run1(&mut self, command: &Command) -> Result<Command> {
+        if self.shell.dry_run {
+            return Ok(dry_run(command.clone()));
+        }
+
+        if command.is_empty() {
+            return Ok(skipped(command.clone()));
+        }
+
+        if let Some(before) = &self.shell.before {
+            (before.0)(command);
+        }
+
+        // Evaluate the command as a quoted string so it runs in the shell itself and `cd`,
+        // `export`, etc. persist, while a syntax error like an unbalanced quote fails the command
+        // instead of leaving the shell waiting for more input; `command` keeps the shell running
+        write!(
+            self.stdin,
+            "command eval '{}' </dev/null\nprintf '{s} %d\\n' $?\nprintf '{s}\\n' >&2\n",
+            command.command.replace('\'', "'\\''"),
+            s = self.sentinel,
+        )?;
+        self.stdin.flush()?;
+
+        let sentinel = self.sentinel.as_str();
+        let stderr = &mut self.stderr;
+        let stdout = &mut self.stdout;
+        let (stdout, stderr) = std::thread::scope(|s| {
+            let stderr = s.spawn(|| read_sentinel(stderr, sentinel));
+            (
+                read_sentinel(stdout, sentinel),
+                stderr.join().expect("join"),
+            )
+        });
+        let ((stdout, code), (stderr, _rest)) = (stdout?, stderr?);
+
+        let mut r = Command {
+            code: code.trim().parse().ok(),
+            ..command.clone()
+        };
+        for (is_stdout, pipe, output) in [
+            (true, &mut r.stdout, stdout),
+            (false, &mut r.stderr, stderr),
+        ] {
+            match pipe {
+                Pipe::String(_) => *pipe = Pipe::String(Some(output)),
+                Pipe::Null => {}
+                Pipe::Stdout => std::io::stdout().write_all(output.as_bytes())?,
+                Pipe::Inherit if is_stdout => std::io::stdout().write_all(output.as_bytes())?,
+                Pipe::Inherit | Pipe::Stderr => std::io::stderr().write_all(output.as_bytes())?,
+            }
         }
+        self.shell.normalize(&mut r);
+
+        if let Some(after) = &self.shell.after {
+            (after.0)(&r);
+        }
+
+        Ok(r)
     }
 }
 
-impl Command {
-    pub fn new(command: &str) -> Command {
-        Command {
-            command: command.to_string(),
-            ..Default::default()
+#[cfg(unix)]
+impl Drop for PersistentShell {
+    fn drop(&mut self) {
+        self.child.kill().ok();
+        self.child.wait().ok();
+    }
+}
+
+/// Read a pipe up to the sentinel; returns the output before it and the rest of its line
+#[cfg(unix)]
+fn read_sentinel(reader: &mut impl std::io::BufRead, sentinel: &str) -> Result<(String, String)> {
+    let mut r = vec![];
+    loop {
+        let start = r.len();
+        if reader.read_until(b'\n', &mut r)? == 0 {
+            return Err(anyhow!("Persistent shell exited!"));
+        }
+        if let Some(i) = r[start..]
+            .windows(sentinel.len())
+            .position(|x| x == sentinel.as_bytes())
+        {
+            let rest = String::from_utf8_lossy(&r[start + i + sentinel.len()..]).into_owned();
+            r.truncate(start + i);
+            return Ok((String::from_utf8_lossy(&r).into_owned(), rest));
         }
     }
 }
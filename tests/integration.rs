@@ -15,21 +15,46 @@ fn manual() {
 
     let shell = Shell {
         shell: Some(String::from("sh -c")),
+        shell_argv: None,
 
         dry_run: false,
         sync: true,
+        keep_going: false,
+        process_group: false,
+        env_clear: false,
+        env_keep: vec![],
         print: true,
+        decorate: true,
+        verbose: false,
+        decorations_to_stderr: false,
+        out: None,
+        fence_empty: true,
+        progress: false,
+        hyperlinks: false,
         color: ColorOverride::default(),
+        batch_timeout: None,
+        expand_env: false,
+        expand_env_strict: false,
+        normalize_newlines: false,
 
         fence: String::from("```"),
         info: String::from("text"),
         prompt: String::from("$ "),
+        wrap_width: None,
+        wrap_operators: false,
+        output_prefix: None,
+        output_files: None,
 
         fence_style: style("#555555").expect("style"),
         info_style: style("#555555").expect("style"),
         prompt_style: style("#555555").expect("style"),
         command_style: style("#00ffff+bold").expect("style"),
         error_style: style("#ff0000+bold+italic").expect("style"),
+        command_gradient: vec![],
+
+        before: None,
+        after: None,
+        renderer: None,
     };
 
     shell.run(&[Command::new("ls *"), Command::new("ls -l")]);
@@ -41,21 +66,46 @@ fn custom() {
 
     let shell = Shell {
         shell: Some(String::from("bash -xeo pipefail -c")),
+        shell_argv: None,
 
         dry_run: false,
         sync: true,
+        keep_going: false,
+        process_group: false,
+        env_clear: false,
+        env_keep: vec![],
         print: true,
+        decorate: true,
+        verbose: false,
+        decorations_to_stderr: false,
+        out: None,
+        fence_empty: true,
+        progress: false,
+        hyperlinks: false,
         color: ColorOverride::default(),
+        batch_timeout: None,
+        expand_env: false,
+        expand_env_strict: false,
+        normalize_newlines: false,
 
         fence: String::from("~~~~"),
         info: String::from("bash"),
         prompt: String::from("> "),
+        wrap_width: None,
+        wrap_operators: false,
+        output_prefix: None,
+        output_files: None,
 
         fence_style: style("#ffff00").expect("style"),
         info_style: style("#ff0000+italic").expect("style"),
         prompt_style: style("#00ff00").expect("style"),
         command_style: style("#ff00ff+bold").expect("style"),
         error_style: style("#00ff00+bold+italic").expect("style"),
+        command_gradient: vec![],
+
+        before: None,
+        after: None,
+        renderer: None,
     };
 
     shell.run(&[Command::new("ls *"), Command::new("ls -l")]);
@@ -67,21 +117,46 @@ fn direct() {
 
     let shell = Shell {
         shell: None,
+        shell_argv: None,
 
         dry_run: false,
         sync: true,
+        keep_going: false,
+        process_group: false,
+        env_clear: false,
+        env_keep: vec![],
         print: true,
+        decorate: true,
+        verbose: false,
+        decorations_to_stderr: false,
+        out: None,
+        fence_empty: true,
+        progress: false,
+        hyperlinks: false,
         color: ColorOverride::default(),
+        batch_timeout: None,
+        expand_env: false,
+        expand_env_strict: false,
+        normalize_newlines: false,
 
         fence: String::from("```"),
         info: String::from("text"),
         prompt: String::from("$ "),
+        wrap_width: None,
+        wrap_operators: false,
+        output_prefix: None,
+        output_files: None,
 
         fence_style: style("#555555").expect("style"),
         info_style: style("#555555").expect("style"),
         prompt_style: style("#555555").expect("style"),
         command_style: style("#00ffff+bold").expect("style"),
         error_style: style("#ff0000+bold+italic").expect("style"),
+        command_gradient: vec![],
+
+        before: None,
+        after: None,
+        renderer: None,
     };
 
     shell.run(&[
@@ -113,6 +188,7 @@ fn pipe_manual() {
             .stdout,
         Pipe::String(Some(String::from(
             "\
+benches
 Cargo.lock
 Cargo.toml
 CHANGELOG.md
@@ -133,6 +209,7 @@ fn pipe1() {
     assert_eq!(
         Shell::default().pipe1("ls"),
         "\
+benches
 Cargo.lock
 Cargo.toml
 CHANGELOG.md
@@ -146,3 +223,2329 @@ tests
         "
     );
 }
+
+#[test]
+fn spawn() {
+    let mut running = Shell {
+        print: false,
+        ..Default::default()
+    }
+    .spawn(&Command {
+        command: String::from("cat"),
+        stdin: Pipe::string(),
+        stdout: Pipe::string(),
+        ..Default::default()
+    })
+    .expect("spawn");
+
+    running.write_stdin(b"hello\n").expect("write stdin");
+
+    let result = running.wait();
+
+    assert_eq!(result.code, Some(0));
+    assert_eq!(result.stdout, Pipe::String(Some(String::from("hello\n"))));
+}
+
+#[test]
+fn fence_empty() {
+    println!();
+
+    let shell = Shell {
+        fence_empty: false,
+        ..Default::default()
+    };
+
+    let results = shell.run(&[Command::new("true"), Command::new("echo hello")]);
+
+    assert_eq!(results[1].code, Some(0));
+    assert_eq!(results[1].stdout, Pipe::Stdout);
+
+    let shell = Shell {
+        color: ColorOverride::Never,
+        ..shell
+    };
+
+    // No fence without output
+    assert_eq!(shell.render(&[Command::new("true")]), "$ true\n\n");
+
+    // The held back commands are printed with the fence before the first output
+    assert_eq!(
+        shell.render(&[
+            Command::new("true"),
+            Command::new("echo out; sleep 0.1; echo err >&2"),
+            Command::new("true"),
+        ]),
+        "```text\n$ true\n\n$ echo out; \\\nsleep 0.1; \\\necho err >&2\nout\nerr\n\n$ true\n```\n\n"
+    );
+
+    // Stdin is printed in order after the held back commands
+    assert_eq!(
+        shell.render(&[
+            Command::new("true"),
+            Command {
+                stdin: Pipe::String(Some(String::from("b\na"))),
+                ..Command::new("sort")
+            },
+        ]),
+        "```text\n$ true\n\n$ sort\n```\n\n```sort\nb\na\n```\n\n```text\na\nb\n```\n\n"
+    );
+}
+
+#[test]
+fn info() {
+    println!();
+
+    let shell = Shell::default();
+
+    shell.run(&[
+        Command::new("ls"),
+        Command {
+            command: String::from("echo '{}'"),
+            info: Some(String::from("json")),
+            ..Default::default()
+        },
+    ]);
+}
+
+#[test]
+fn run_markdown() {
+    assert_eq!(
+        Shell::default().run_markdown(&[Command::new("echo hello"), Command::new("true")]),
+        "\
+```text
+$ echo hello
+hello
+```
+
+```text
+$ true
+```
+
+\
+        "
+    );
+
+    let commands = [Command::new("exit 2"), Command::new("echo after")];
+    let shell = Shell {
+        color: ColorOverride::Never,
+        ..Default::default()
+    };
+
+    // Stops at the first failure unless keep going
+    assert!(!shell.run_markdown(&commands).contains("after"));
+    assert_eq!(
+        Shell {
+            keep_going: true,
+            ..shell
+        }
+        .run_markdown(&commands),
+        "\
+```text
+$ exit 2
+```
+
+**Command `exit 2` exited with code: `2`!**
+
+```text
+$ echo after
+after
+```
+
+\
+        "
+    );
+}
+
+#[test]
+fn decorate() {
+    println!();
+
+    let shell = Shell {
+        decorate: false,
+        ..Default::default()
+    };
+
+    shell.run(&[Command::new("ls"), Command::new("ls -l")]);
+}
+
+#[test]
+fn exec() {
+    assert_eq!(
+        Shell {
+            print: false,
+            ..Default::default()
+        }
+        .run(&[Command {
+            stdout: Pipe::string(),
+            ..Command::exec(vec!["echo", "a  b", "$HOME", "'c'"])
+        }])[0]
+            .stdout,
+        Pipe::String(Some(String::from("a  b $HOME 'c'\n"))),
+    );
+}
+
+#[test]
+fn unterminated_quote() {
+    let shell = Shell {
+        shell: None,
+        print: false,
+        ..Default::default()
+    };
+
+    assert!(shell.run1(&Command::new("echo \"unterminated")).is_err());
+
+    let results = shell.run(&[Command::new("echo \"unterminated"), Command::new("true")]);
+
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].code, None);
+}
+
+#[test]
+fn empty() {
+    for shell in [Some(String::from("sh -c")), None] {
+        let shell = Shell {
+            shell,
+            print: false,
+            ..Default::default()
+        };
+
+        for command in ["", "   "] {
+            let result = shell
+                .run1(&Command {
+                    stdout: Pipe::string(),
+                    ..Command::new(command)
+                })
+                .expect("run1");
+
+            assert_eq!(result.code, Some(0));
+            assert_eq!(result.stdout, Pipe::String(Some(String::new())));
+        }
+    }
+}
+
+#[test]
+fn quote() {
+    let s = "it's a \"$pattern\"; `ls` && \\ *";
+
+    assert_eq!(
+        Shell {
+            print: false,
+            ..Default::default()
+        }
+        .pipe1(&format!("printf %s {}", sprint::quote(s))),
+        s,
+    );
+}
+
+#[test]
+fn capture_large_stderr() {
+    let shell = Shell {
+        print: false,
+        ..Default::default()
+    };
+
+    // More than a pipe buffer of stderr before any stdout
+    let output = shell
+        .capture("head -c 200000 /dev/zero | tr '\\0' x >&2; echo done")
+        .unwrap();
+    assert_eq!(output.code, Some(0));
+    assert_eq!(output.stdout, "done\n");
+    assert_eq!(output.stderr, "x".repeat(200_000));
+}
+
+#[test]
+fn capture_invalid_utf8() {
+    let output = Shell {
+        print: false,
+        ..Default::default()
+    }
+    .capture("printf '\\377\\376'; printf '\\377' >&2")
+    .unwrap();
+
+    // Invalid UTF-8 is replaced instead of panicking
+    assert_eq!(output.code, Some(0));
+    assert_eq!(output.stdout, "\u{fffd}\u{fffd}");
+    assert_eq!(output.stderr, "\u{fffd}");
+}
+
+#[test]
+fn capture_dry_run() {
+    assert_eq!(
+        Shell {
+            dry_run: true,
+            ..Default::default()
+        }
+        .capture("echo hello")
+        .expect("capture"),
+        Output {
+            code: Some(0),
+            ..Default::default()
+        },
+    );
+}
+
+#[test]
+fn process_group() {
+    let mut running = Shell {
+        print: false,
+        process_group: true,
+        ..Default::default()
+    }
+    .spawn(&Command::new("sleep 30 & wait"))
+    .expect("spawn");
+
+    running.kill().expect("kill");
+
+    assert_eq!(running.wait().code, None);
+}
+
+#[test]
+fn run_iter() {
+    let results = Shell {
+        print: false,
+        ..Default::default()
+    }
+    .run_iter((1..=3).map(|i| Command {
+        stdout: Pipe::string(),
+        ..Command::new(&format!("echo {i}"))
+    }));
+
+    assert_eq!(
+        results
+            .into_iter()
+            .map(|x| x.stdout.into_string())
+            .collect::<Vec<_>>(),
+        vec!["1\n", "2\n", "3\n"],
+    );
+}
+
+#[test]
+fn stdin_null() {
+    let shell = Shell {
+        print: false,
+        ..Default::default()
+    };
+
+    let result = shell
+        .run1(&Command {
+            stdout: Pipe::string(),
+            ..Command::new("cat; echo done")
+        })
+        .expect("run1");
+
+    assert_eq!(result.stdout, Pipe::String(Some(String::from("done\n"))));
+}
+
+#[test]
+fn stdin_inherit() {
+    // Run sprint itself with a string stdin; its commands inherit that stdin
+    let result = Shell {
+        print: false,
+        ..Default::default()
+    }
+    .run1(&Command {
+        stdin: Pipe::String(Some(String::from("hello\n"))),
+        stdout: Pipe::string(),
+        ..Command::exec([env!("CARGO_BIN_EXE_sprint"), "-C", "never", "cat"])
+    })
+    .expect("run1");
+
+    assert_eq!(
+        result.stdout,
+        Pipe::String(Some(String::from("```text\n$ cat\nhello\n```\n\n"))),
+    );
+}
+
+#[test]
+fn stdin_large() {
+    let shell = Shell {
+        print: false,
+        ..Default::default()
+    };
+
+    // More stdin than fits in a pipe, streamed back while it is written
+    let stdin = "x".repeat(1_000_000);
+    let command = Command {
+        stdin: Pipe::String(Some(stdin.clone())),
+        stdout: Pipe::string(),
+        ..Command::new("cat")
+    };
+
+    let result = shell.run1(&command).expect("run1");
+    assert_eq!(result.stdout, Pipe::String(Some(stdin.clone())));
+
+    for batch_timeout in [None, Some(std::time::Duration::from_secs(60))] {
+        let results = Shell {
+            batch_timeout,
+            ..shell.clone()
+        }
+        .run_owned(vec![command.clone()]);
+        assert_eq!(results[0].stdout, Pipe::String(Some(stdin.clone())));
+        assert_eq!(results[0].stdin, command.stdin);
+    }
+
+    // A command that does not read its stdin still succeeds
+    let result = shell
+        .run1(&Command {
+            stdin: Pipe::String(Some(stdin)),
+            ..Command::new("true")
+        })
+        .expect("run1");
+    assert_eq!(result.code, Some(0));
+}
+
+#[test]
+fn allow_failure() {
+    let results = Shell {
+        print: false,
+        ..Default::default()
+    }
+    .run(&[
+        Command {
+            allow_failure: true,
+            ..Command::new("exit 3")
+        },
+        Command::new("true"),
+    ]);
+
+    assert_eq!(
+        results.iter().map(|x| x.code).collect::<Vec<_>>(),
+        vec![Some(3), Some(0)],
+    );
+}
+
+#[test]
+fn out() {
+    let buffer = std::sync::Arc::new(std::sync::Mutex::new(vec![]));
+
+    let shell = Shell {
+        out: Some(Writer(buffer.clone())),
+        ..Default::default()
+    };
+
+    shell.run(&[Command::new("echo a"), Command::new("echo b >&2; exit 2")]);
+
+    assert_eq!(
+        String::from_utf8(buffer.lock().unwrap().clone()).unwrap(),
+        "\
+```text
+$ echo a
+a
+
+$ echo b >&2; \\
+exit 2
+b
+```
+
+**Command `echo b >&2; exit 2` exited with code: `2`!**
+
+\
+        ",
+    );
+}
+
+#[test]
+fn render() {
+    let shell = Shell {
+        color: ColorOverride::Always,
+        ..Default::default()
+    };
+
+    let transcript = shell.render(&[Command::new("echo hello")]);
+
+    assert!(transcript.contains("\x1b["));
+    assert!(transcript.contains("hello\n"));
+}
+
+#[test]
+fn wrap_width() {
+    let shell = Shell {
+        wrap_width: Some(20),
+        ..Default::default()
+    };
+
+    assert_eq!(
+        shell.run_markdown(&[Command::new("echo aaaa bbbb cccc dddd eeee")]),
+        "\
+```text
+$ echo aaaa bbbb \\
+    cccc dddd eeee
+aaaa bbbb cccc dddd eeee
+```
+
+\
+        ",
+    );
+}
+
+#[test]
+fn wrap_pipes() {
+    let command = Command::new("echo a | tr a b || echo c");
+
+    assert_eq!(
+        Shell::default().run_markdown(std::slice::from_ref(&command)),
+        "\
+```text
+$ echo a | tr a b \\
+|| echo c
+b
+```
+
+\
+        ",
+    );
+
+    let shell = Shell {
+        wrap_operators: true,
+        ..Default::default()
+    };
+
+    assert_eq!(
+        shell.run_markdown(&[command]),
+        "\
+```text
+$ echo a \\
+| tr a b \\
+|| echo c
+b
+```
+
+\
+        ",
+    );
+}
+
+#[test]
+fn partial_eq() {
+    assert_eq!(Shell::default(), Shell::default());
+
+    assert_ne!(
+        Shell {
+            sync: false,
+            ..Default::default()
+        },
+        Shell::default(),
+    );
+
+    assert_ne!(
+        Shell {
+            command_style: style("#00ffff").expect("style"),
+            ..Default::default()
+        },
+        Shell::default(),
+    );
+}
+
+#[test]
+fn style_to_string() {
+    for spec in [
+        "",
+        "#555555",
+        "#00ffff+bold",
+        "#ff0000+bold+italic",
+        "red",
+        "bright-white+on-black",
+        "on-#102030+underline",
+        "on-bright-magenta+dimmed+blink+blink_fast+reversed+hidden+strikethrough",
+    ] {
+        let s = style(spec).unwrap();
+        assert_eq!(sprint::style_to_string(&s), spec);
+        assert_eq!(style(&sprint::style_to_string(&s)).unwrap(), s);
+    }
+    assert_eq!(
+        sprint::style_to_string(&style("purple+on-bright-purple").unwrap()),
+        "magenta+on-bright-magenta",
+    );
+}
+
+#[test]
+fn command_shell() {
+    let shell = Shell {
+        print: false,
+        ..Default::default()
+    };
+
+    let command = Command {
+        stdout: Pipe::string(),
+        ..Command::new("echo $0")
+    };
+
+    let results = shell.run(&[
+        command.clone(),
+        Command {
+            shell: Some(None),
+            ..command.clone()
+        },
+        Command {
+            shell: Some(Some(String::from("sh -c"))),
+            ..command.clone()
+        },
+    ]);
+
+    assert_eq!(results[0].stdout, Pipe::String(Some(String::from("sh\n"))));
+    assert_eq!(results[1].stdout, Pipe::String(Some(String::from("$0\n"))));
+    assert_eq!(results[2].stdout, Pipe::String(Some(String::from("sh\n"))));
+
+    let shell = Shell {
+        shell: None,
+        ..shell
+    };
+
+    assert_eq!(
+        shell.run1(&command).unwrap().stdout,
+        Pipe::String(Some(String::from("$0\n"))),
+    );
+    assert_eq!(
+        shell
+            .run1(&Command {
+                shell: Some(Some(String::from("sh -c"))),
+                ..command.clone()
+            })
+            .unwrap()
+            .stdout,
+        Pipe::String(Some(String::from("sh\n"))),
+    );
+}
+
+#[test]
+fn batch_timeout() {
+    let shell = Shell {
+        batch_timeout: Some(std::time::Duration::from_millis(500)),
+        process_group: true,
+        print: false,
+        ..Default::default()
+    };
+
+    let commands = [
+        Command::new("true"),
+        Command {
+            stdout: Pipe::string(),
+            ..Command::new("echo a; sleep 5")
+        },
+        Command::new("true"),
+    ];
+
+    let start = std::time::Instant::now();
+    let results = shell.run(&commands);
+
+    assert!(start.elapsed() < std::time::Duration::from_secs(4));
+    assert_eq!(results.len(), 2);
+    assert_eq!(results[0].code, Some(0));
+    assert_eq!(results[1].code, None);
+
+    assert!(Shell {
+        print: true,
+        ..shell
+    }
+    .render(&commands)
+    .ends_with("**Batch timeout of 500ms exceeded after 1 command(s) completed!**\n\n"));
+}
+
+#[test]
+fn pid() {
+    let shell = Shell {
+        print: false,
+        ..Default::default()
+    };
+
+    let result = shell
+        .run1(&Command {
+            stdout: Pipe::string(),
+            ..Command::new("echo $$")
+        })
+        .expect("run1");
+
+    assert_eq!(
+        result.stdout,
+        Pipe::String(Some(format!("{}\n", result.pid.expect("pid")))),
+    );
+
+    let output = shell.capture("echo $$").expect("capture");
+
+    assert_eq!(output.stdout, format!("{}\n", output.pid.expect("pid")));
+
+    assert_eq!(
+        Shell {
+            dry_run: true,
+            ..shell
+        }
+        .run1(&Command::new("true"))
+        .expect("run1")
+        .pid,
+        None,
+    );
+}
+
+#[test]
+fn results_ok() {
+    let shell = Shell {
+        print: false,
+        ..Default::default()
+    };
+
+    let results = shell.run(&[
+        Command {
+            codes: vec![1],
+            ..Command::new("exit 1")
+        },
+        Command {
+            allow_failure: true,
+            ..Command::new("exit 2")
+        },
+    ]);
+
+    assert!(results.iter().all(Command::succeeded));
+    assert!(sprint::results_ok(&results));
+
+    let results = shell.run(&[Command::new("kill -9 $$")]);
+
+    assert_eq!(results[0].code, None);
+    assert!(!sprint::results_ok(&results));
+}
+
+#[test]
+fn output_prefix() {
+    let shell = Shell {
+        output_prefix: Some(String::from("| ")),
+        color: ColorOverride::Never,
+        ..Default::default()
+    };
+
+    assert_eq!(
+        shell.render(&[Command::new("echo a; echo b"), Command::new("echo c")]),
+        "\
+```text
+$ echo a; \\
+echo b
+| a
+| b
+
+$ echo c
+| c
+```
+
+\
+        ",
+    );
+}
+
+#[test]
+fn codes_range() {
+    let results = Shell {
+        print: false,
+        ..Default::default()
+    }
+    .run(&[
+        Command::new("exit 4").codes_range(0..=4),
+        Command::new("exit 5").codes_range(0..=4),
+        Command::new("true"),
+    ]);
+
+    assert_eq!(results.len(), 2);
+    assert!(results[0].succeeded());
+    assert!(!results[1].succeeded());
+}
+
+#[test]
+fn from_str() {
+    let shell = Shell {
+        print: false,
+        ..Default::default()
+    };
+
+    let results = shell.run(&["true".into(), String::from("exit 2").into()]);
+
+    assert_eq!(results[0].command, "true");
+    assert_eq!(results[1].command, "exit 2");
+    assert_eq!(results[1].code, Some(2));
+
+    let results = shell.run_iter(vec!["echo a", "echo b"]);
+
+    assert_eq!(results.len(), 2);
+    assert!(sprint::results_ok(&results));
+}
+
+#[test]
+fn keep_going() {
+    let shell = Shell {
+        keep_going: true,
+        color: ColorOverride::Never,
+        ..Default::default()
+    };
+
+    let commands = ["true".into(), "exit 2".into(), "echo a".into()];
+
+    assert_eq!(
+        shell.render(&commands),
+        "\
+```text
+$ true
+
+$ exit 2
+
+$ echo a
+a
+```
+
+**Command `exit 2` exited with code: `2`!**
+
+2 succeeded, 1 failed
+
+\
+        ",
+    );
+}
+
+#[test]
+fn interactive_prompt_result() {
+    let buffer = std::sync::Arc::new(std::sync::Mutex::new(vec![]));
+
+    let shell = Shell {
+        out: Some(Writer(buffer.clone())),
+        ..Default::default()
+    };
+
+    shell.interactive_prompt_result(&shell.core(Command::new("exit 2")).unwrap());
+    shell.interactive_prompt_result(&shell.core(Command::new("true")).unwrap());
+
+    assert_eq!(
+        String::from_utf8(buffer.lock().unwrap().clone()).unwrap(),
+        "```\n\n```text\n[2] $ ```\n\n```text\n$ ",
+    );
+}
+
+#[test]
+fn run_until_error() {
+    let shell = Shell {
+        print: false,
+        ..Default::default()
+    };
+
+    let (results, failed) = shell.run_until_error(&["true".into(), "echo a".into()]);
+
+    assert_eq!(results.len(), 2);
+    assert_eq!(failed, None);
+
+    let (results, failed) = shell.run_until_error(&[
+        Command {
+            allow_failure: true,
+            ..Command::new("false")
+        },
+        "kill -9 $$".into(),
+        "true".into(),
+    ]);
+
+    assert_eq!(results.len(), 1);
+    assert_eq!(failed.unwrap().code, None);
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn run_async() {
+    let shell = Shell {
+        print: false,
+        ..Default::default()
+    };
+
+    let commands = [
+        Command {
+            stdin: Pipe::String(Some(String::from("a\n"))),
+            stdout: Pipe::string(),
+            ..Command::new("cat")
+        },
+        Command::new(""),
+        Command::new("exit 2"),
+        Command::new("true"),
+    ];
+
+    let results = shell.run_async(&commands).await;
+
+    assert_eq!(results.len(), 3);
+    assert_eq!(results[0].stdout, Pipe::String(Some(String::from("a\n"))));
+    assert!(results[0].pid.is_some());
+    assert_eq!(results[1].code, Some(0));
+    assert_eq!(results[2].code, Some(2));
+
+    let results = Shell {
+        sync: false,
+        ..shell.clone()
+    }
+    .run_async(&commands)
+    .await;
+
+    assert_eq!(results.len(), 4);
+    assert_eq!(results[3].code, Some(0));
+
+    // More stdin than fits in a pipe is written while the output is read
+    let stdin = "x".repeat(1_000_000);
+    let results = shell
+        .run_async(&[
+            Command {
+                stdin: Pipe::String(Some(stdin.clone())),
+                stdout: Pipe::string(),
+                ..Command::new("cat")
+            },
+            Command {
+                stdin: Pipe::String(Some(stdin.clone())),
+                ..Command::new("true")
+            },
+        ])
+        .await;
+
+    assert_eq!(results[0].stdout, Pipe::String(Some(stdin)));
+    assert_eq!(results[1].code, Some(0));
+
+    // Errors running commands in parallel are printed
+    let buffer = std::sync::Arc::new(std::sync::Mutex::new(vec![]));
+    let results = Shell {
+        shell: None,
+        sync: false,
+        print: true,
+        out: Some(Writer(buffer.clone())),
+        ..shell
+    }
+    .run_async(&[Command::new("echo 'a")])
+    .await;
+    assert_eq!(results[0].code, None);
+    assert!(String::from_utf8(buffer.lock().unwrap().clone())
+        .unwrap()
+        .contains("Failed to parse command: \"echo 'a\"!"));
+}
+
+#[test]
+fn capture_limit() {
+    let shell = Shell {
+        print: false,
+        ..Default::default()
+    };
+
+    let result = shell
+        .run1(&Command {
+            stdout: Pipe::string(),
+            capture_limit: Some(4),
+            ..Command::new("yes")
+        })
+        .expect("run1");
+
+    assert_eq!(result.stdout, Pipe::String(Some(String::from("y\ny\n"))));
+    assert!(result.truncated);
+
+    let result = shell
+        .run1(&Command {
+            stdout: Pipe::string(),
+            stderr: Pipe::string(),
+            capture_limit: Some(4),
+            ..Command::new("echo ab >&2; echo abcd")
+        })
+        .expect("run1");
+
+    assert_eq!(result.stdout, Pipe::String(Some(String::from("abcd"))));
+    assert_eq!(result.stderr, Pipe::String(Some(String::from("ab\n"))));
+    assert!(result.truncated);
+
+    // Also killed before the deadline
+    let start = std::time::Instant::now();
+    let result = Shell {
+        batch_timeout: Some(std::time::Duration::from_secs(10)),
+        ..shell
+    }
+    .run(&[Command {
+        stdout: Pipe::string(),
+        capture_limit: Some(4),
+        ..Command::new("yes a")
+    }]);
+
+    assert!(start.elapsed() < std::time::Duration::from_secs(5));
+    assert_eq!(result[0].stdout, Pipe::String(Some(String::from("a\na\n"))));
+    assert!(result[0].truncated);
+    assert!(!result[0].succeeded());
+}
+
+#[test]
+fn color_override_from_str() {
+    for color in [
+        ColorOverride::Auto,
+        ColorOverride::Always,
+        ColorOverride::Never,
+    ] {
+        assert_eq!(color.to_string().parse::<ColorOverride>().unwrap(), color);
+        assert_eq!(
+            color
+                .to_string()
+                .to_uppercase()
+                .parse::<ColorOverride>()
+                .unwrap(),
+            color
+        );
+    }
+
+    assert_eq!(
+        "sometimes"
+            .parse::<ColorOverride>()
+            .unwrap_err()
+            .to_string(),
+        "Invalid color: \"sometimes\"! Expected `auto`, `always`, or `never`.",
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn nice_umask() {
+    let result = Shell {
+        print: false,
+        ..Default::default()
+    }
+    .run1(&Command {
+        stdout: Pipe::string(),
+        nice: Some(19),
+        umask: Some(0o027),
+        ..Command::new("nice; umask")
+    })
+    .expect("run1");
+
+    assert_eq!(
+        result.stdout,
+        Pipe::String(Some(String::from("19\n0027\n")))
+    );
+}
+
+#[test]
+fn renderer() {
+    struct Indent;
+
+    impl Renderer for Indent {
+        fn open(&self, _shell: &Shell, info: &str) -> String {
+            format!("[{info}]\n")
+        }
+
+        fn close(&self, _shell: &Shell) -> String {
+            String::from("[end]\n")
+        }
+
+        fn command(&self, _shell: &Shell, command: &Command) -> String {
+            format!("> {}\n", command.command)
+        }
+
+        fn error(&self, _shell: &Shell, error: &str) -> String {
+            format!("! {error}\n")
+        }
+
+        fn output(&self, _shell: &Shell, output: &str) -> String {
+            output.lines().map(|x| format!("  {x}\n")).collect()
+        }
+
+        fn separator(&self, _shell: &Shell) -> String {
+            String::new()
+        }
+    }
+
+    let shell = Shell {
+        renderer: Some(std::sync::Arc::new(Indent)),
+        color: ColorOverride::Never,
+        ..Default::default()
+    };
+
+    assert_eq!(
+        shell.render(&[
+            Command::new("echo a"),
+            Command::new("echo b; exit 3"),
+            Command::new("echo c"),
+        ]),
+        "[text]\n> echo a\n  a\n> echo b; exit 3\n  b\n[end]\n! Command `echo b; exit 3` exited with code: `3`!\n",
+    );
+}
+
+#[test]
+fn resolve() {
+    let shell = Shell::default();
+
+    assert_eq!(
+        shell
+            .resolve(&Command {
+                argv: Some(vec![String::from("printf"), String::from("%s $x")]),
+                ..Default::default()
+            })
+            .unwrap(),
+        (String::from("printf"), vec![String::from("%s $x")]),
+    );
+
+    assert_eq!(
+        shell
+            .resolve(&Command {
+                shell: Some(None),
+                ..Command::new("echo 'a  b' c")
+            })
+            .unwrap(),
+        (
+            String::from("echo"),
+            vec![String::from("a  b"), String::from("c")]
+        ),
+    );
+
+    assert!(shell
+        .resolve(&Command {
+            shell: Some(None),
+            ..Command::new("")
+        })
+        .is_err());
+}
+
+#[test]
+fn expand_env() {
+    // Set variables in a child process instead of mutating this process' environment
+    if std::env::var_os("SPRINT_TEST_CHILD").is_none() {
+        child(
+            "expand_env",
+            &[
+                ("SPRINT_EXPAND_TEST", "a b"),
+                ("SPRINT_EXPAND_QUOTE", "it's \"$HOME\" | x"),
+            ],
+        );
+        return;
+    }
+
+    let shell = Shell {
+        shell: None,
+        expand_env: true,
+        ..Default::default()
+    };
+
+    assert_eq!(
+        shell
+            .resolve(&Command::new(
+                "printf '%s|' $SPRINT_EXPAND_TEST \"${SPRINT_EXPAND_TEST}\""
+            ))
+            .unwrap(),
+        (
+            String::from("printf"),
+            vec![
+                String::from("%s|"),
+                String::from("a"),
+                String::from("b"),
+                String::from("a b"),
+            ]
+        ),
+    );
+
+    // Values are never parsed as syntax
+    assert_eq!(
+        shell
+            .resolve(&Command::new(
+                "echo \"$SPRINT_EXPAND_QUOTE\" $SPRINT_EXPAND_QUOTE '$SPRINT_EXPAND_QUOTE'"
+            ))
+            .unwrap()
+            .1,
+        vec![
+            String::from("it's \"$HOME\" | x"),
+            String::from("it's"),
+            String::from("\"$HOME\""),
+            String::from("|"),
+            String::from("x"),
+            String::from("$SPRINT_EXPAND_QUOTE"),
+        ],
+    );
+
+    // Unknown variables are empty unless strict
+    assert_eq!(
+        shell
+            .resolve(&Command::new("echo x${SPRINT_EXPAND_UNSET}y"))
+            .unwrap()
+            .1,
+        vec![String::from("xy")],
+    );
+    assert!(Shell {
+        expand_env_strict: true,
+        ..shell.clone()
+    }
+    .resolve(&Command::new("echo $SPRINT_EXPAND_UNSET"))
+    .is_err());
+
+    // Per-command override
+    assert_eq!(
+        shell
+            .resolve(&Command {
+                expand_env: Some(false),
+                ..Command::new("echo $SPRINT_EXPAND_TEST")
+            })
+            .unwrap()
+            .1,
+        vec![String::from("$SPRINT_EXPAND_TEST")],
+    );
+}
+
+#[test]
+fn run1_async_with() {
+    let path = std::env::temp_dir().join(format!("sprint-spawn-{}", std::process::id()));
+    let file = std::fs::File::create(&path).unwrap();
+
+    let shell = Shell {
+        print: false,
+        ..Default::default()
+    };
+
+    let status = shell
+        .run1_async_with(
+            &Command::new("echo out; echo err >&2"),
+            SpawnOptions {
+                stdout: Some(file.try_clone().unwrap().into()),
+                stderr: Some(file.into()),
+                ..Default::default()
+            },
+        )
+        .unwrap()
+        .wait()
+        .unwrap();
+
+    assert!(status.success());
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "out\nerr\n");
+
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn which() {
+    let shell = Shell::default();
+
+    let sh = shell.which("sh").unwrap();
+    assert!(sh.is_absolute());
+    assert_eq!(shell.which(sh.to_str().unwrap()), Some(sh));
+    assert_eq!(shell.which(""), None);
+    assert_eq!(shell.which("not-a-real-command"), None);
+
+    // Missing programs yield a friendly error when the shell is disabled
+    let e = Shell {
+        shell: None,
+        print: false,
+        ..Default::default()
+    }
+    .run1(&Command::new("not-a-real-command --flag"))
+    .unwrap_err();
+    assert_eq!(e.to_string(), "Program `not-a-real-command` not found!");
+}
+
+#[test]
+fn missing_shell() {
+    let shell = Shell {
+        shell: Some(String::from("not-a-real-shell -c")),
+        print: false,
+        ..Default::default()
+    };
+
+    assert_eq!(
+        shell.run1(&Command::new("true")).unwrap_err().to_string(),
+        "Shell program `not-a-real-shell` not found; is it installed?",
+    );
+}
+
+#[test]
+fn run_async_each() {
+    let shell = Shell {
+        print: false,
+        ..Default::default()
+    };
+
+    let mut results = vec![];
+
+    shell.run_async_each(
+        &[
+            Command::new("sleep 0.3; exit 3"),
+            Command::new("true"),
+            Command::new("exit 2"),
+        ],
+        |result| results.push((result.command, result.code)),
+    );
+
+    results.sort();
+    assert_eq!(
+        results,
+        vec![
+            (String::from("exit 2"), Some(2)),
+            (String::from("sleep 0.3; exit 3"), Some(3)),
+            (String::from("true"), Some(0)),
+        ]
+    );
+}
+
+#[test]
+fn parallel_errors() {
+    let buffer = std::sync::Arc::new(std::sync::Mutex::new(vec![]));
+
+    let shell = Shell {
+        shell: None,
+        sync: false,
+        out: Some(Writer(buffer.clone())),
+        ..Default::default()
+    };
+
+    let results = shell.run(&[Command::new("echo 'a"), Command::new("true")]);
+    assert_eq!(results[0].code, None);
+    assert_eq!(results[1].code, Some(0));
+
+    let mut results = vec![];
+    shell.run_async_each(&[Command::new("echo 'b")], |result| results.push(result));
+    assert_eq!(results[0].code, None);
+
+    let output = String::from_utf8(buffer.lock().unwrap().clone()).unwrap();
+    assert!(output.contains("Failed to parse command: \"echo 'a\"!"));
+    assert!(output.contains("Failed to parse command: \"echo 'b\"!"));
+}
+
+#[test]
+fn normalize_newlines() {
+    let shell = Shell {
+        print: false,
+        normalize_newlines: true,
+        ..Default::default()
+    };
+
+    let command = Command {
+        stdout: Pipe::string(),
+        stderr: Pipe::string(),
+        ..Command::new("printf 'a\\r\\nb\\r\\n'; printf 'c\\r\\n' >&2")
+    };
+
+    let result = shell.run1(&command).unwrap();
+    assert_eq!(result.stdout, Pipe::String(Some(String::from("a\nb\n"))));
+    assert_eq!(result.stderr, Pipe::String(Some(String::from("c\n"))));
+
+    // Raw output is preserved by default or if disabled per command
+    let result = shell
+        .run1(&Command {
+            normalize_newlines: Some(false),
+            ..command
+        })
+        .unwrap();
+    assert_eq!(
+        result.stdout,
+        Pipe::String(Some(String::from("a\r\nb\r\n")))
+    );
+}
+
+#[test]
+fn pipe1_trimmed() {
+    let shell = Shell::default();
+
+    assert_eq!(shell.pipe1("printf 'abc\\n\\n'"), "abc\n\n");
+    assert_eq!(shell.pipe1_trimmed("printf 'abc\\n\\n'"), "abc");
+    assert_eq!(shell.pipe1_trimmed("printf 'a b \\t\\r\\n'"), "a b");
+    assert_eq!(shell.pipe1_trimmed("true"), "");
+}
+
+#[test]
+fn run_target() {
+    let targets = parse_targets(include_str!("../Makefile.md"));
+
+    assert_eq!(
+        targets["clippy"].commands,
+        [Command::new("cargo clippy -- -D clippy::all")]
+    );
+    assert!(targets["all"].commands.is_empty());
+
+    let shell = Shell::null();
+
+    assert_eq!(
+        shell.run_target(&targets, "clippy").unwrap(),
+        targets["clippy"]
+            .commands
+            .iter()
+            .map(|x| Command {
+                dry_run: true,
+                ..x.clone()
+            })
+            .collect::<Vec<_>>()
+    );
+
+    // Backticked items are dependencies if they name a target and files otherwise
+    assert_eq!(
+        targets["target/release/{dirname}"].dependencies,
+        ["README.md"]
+    );
+    assert_eq!(
+        targets["target/release/{dirname}"].files,
+        ["Cargo.lock", "Cargo.toml", "**/*.rs"]
+    );
+    let commands = |target| {
+        shell
+            .run_target(&targets, target)
+            .unwrap()
+            .into_iter()
+            .map(|x| x.command)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        commands("build"),
+        [
+            "cargo build --release",
+            "kapow {0} >{target}",
+            "cargo build --release",
+        ]
+    );
+    assert_eq!(
+        commands("all"),
+        [
+            "cargo clippy -- -D clippy::all",
+            "cargo test -- --test-threads=1",
+            "cargo build --release",
+            "kapow {0} >{target}",
+            "cargo build --release",
+            "cargo doc",
+        ]
+    );
+
+    assert!(shell
+        .run_target(&targets, "not-a-target")
+        .unwrap_err()
+        .to_string()
+        .contains(" `all`, `audit`, `build`, `check`, `clean`, `clippy`, "));
+    assert_eq!(
+        shell
+            .run_target(&Default::default(), "all")
+            .unwrap_err()
+            .to_string(),
+        "Target `all` not found! Available targets: none.",
+    );
+}
+
+#[test]
+fn output_files() {
+    let dir = std::env::temp_dir().join(format!("sprint-output-files-{}", std::process::id()));
+    let prefix = dir.join("step");
+
+    let shell = Shell {
+        print: false,
+        output_files: Some(prefix.clone()),
+        ..Default::default()
+    };
+
+    let results = shell.run(&[
+        Command::new("echo a; echo b >&2"),
+        Command {
+            stdout: Pipe::string(),
+            ..Command::new("echo c")
+        },
+    ]);
+
+    let read = |name: &str| std::fs::read_to_string(dir.join(name)).unwrap();
+    assert_eq!(read("step-0.out"), "a\n");
+    assert_eq!(read("step-0.err"), "b\n");
+    assert_eq!(read("step-1.out"), "c\n");
+    assert_eq!(read("step-1.err"), "");
+
+    // Captured output is still returned
+    assert_eq!(results[0].stdout, Pipe::Stdout);
+    assert_eq!(results[1].stdout, Pipe::String(Some(String::from("c\n"))));
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn verbose() {
+    let shell = Shell {
+        verbose: true,
+        color: ColorOverride::Never,
+        ..Default::default()
+    };
+
+    assert_eq!(
+        shell.render(&[Command::new("echo 'a  b'")]),
+        "```text\n$ echo 'a  b'\n[\"sh\", \"-c\", \"echo 'a  b'\"]\na  b\n```\n\n",
+    );
+
+    let shell = Shell {
+        shell: None,
+        ..shell
+    };
+
+    assert_eq!(
+        shell.render(&[Command::new("echo 'a  b'")]),
+        "```text\n$ echo 'a  b'\n[\"echo\", \"a  b\"]\na  b\n```\n\n",
+    );
+}
+
+#[test]
+fn is_success() {
+    let command = Command {
+        codes: vec![0, 3],
+        ..Command::new("sleep 5")
+    };
+
+    assert!(command.is_success(Some(0)));
+    assert!(command.is_success(Some(3)));
+    assert!(!command.is_success(Some(1)));
+    assert!(!command.is_success(None));
+
+    // Killed by a signal
+    let mut running = Shell::default().spawn(&command).unwrap();
+    running.kill().unwrap();
+    let result = running.wait();
+    assert_eq!(result.code, None);
+    assert!(!result.is_success(result.code));
+    assert!(!result.succeeded());
+    assert!(Command {
+        allow_failure: true,
+        ..result
+    }
+    .succeeded());
+}
+
+#[test]
+fn run_piped() {
+    let shell = Shell {
+        print: false,
+        ..Default::default()
+    };
+
+    let results = shell.run_piped(&[
+        Command::new("echo hello"),
+        Command::new("tr a-z A-Z"),
+        Command::new("exit 4"),
+        Command::new("cat"),
+    ]);
+
+    assert_eq!(results.len(), 3);
+    assert_eq!(
+        results[1].stdin,
+        Pipe::String(Some(String::from("hello\n")))
+    );
+    assert_eq!(
+        results[1].stdout,
+        Pipe::String(Some(String::from("HELLO\n")))
+    );
+    assert_eq!(results[2].code, Some(4));
+
+    let results = Shell {
+        keep_going: true,
+        ..shell
+    }
+    .run_piped(&[Command::new("echo hello; exit 4"), Command::new("cat")]);
+
+    assert_eq!(results.len(), 2);
+    assert_eq!(
+        results[1].stdout,
+        Pipe::String(Some(String::from("hello\n")))
+    );
+}
+
+#[test]
+fn results_map() {
+    let shell = Shell {
+        print: false,
+        keep_going: true,
+        ..Default::default()
+    };
+
+    let results = sprint::results_map(shell.run(&[
+        Command::new("exit 1"),
+        Command::new("true"),
+        Command {
+            codes: vec![1],
+            ..Command::new("exit 1")
+        },
+    ]));
+
+    assert_eq!(results.len(), 2);
+    assert!(results["true"].succeeded());
+    assert!(results["exit 1"].succeeded());
+}
+
+#[test]
+fn style_aliases() {
+    assert_eq!(
+        style("#ff0000+b+i+dim+ul+strike+blink-fast").unwrap(),
+        style("#ff0000+bold+italic+dimmed+underline+strikethrough+blink_fast").unwrap(),
+    );
+    assert_eq!(style("under").unwrap(), style("underline").unwrap());
+    assert_eq!(
+        style("bold+u").unwrap_err().to_string(),
+        "Invalid style spec token: \"u\"!",
+    );
+}
+
+#[test]
+fn command_gradient() {
+    let shell = Shell {
+        command_gradient: vec![style("red").unwrap(), style("green").unwrap()],
+        prompt_style: style("").unwrap(),
+        fence_style: style("").unwrap(),
+        info_style: style("").unwrap(),
+        color: ColorOverride::Always,
+        ..Default::default()
+    };
+
+    assert_eq!(
+        shell.render(&[Command::new("true x")]),
+        "```text\n$ \x1b[31mt\x1b[0m\x1b[32mr\x1b[0m\x1b[31mu\x1b[0m\x1b[32me\x1b[0m \
+        \x1b[31mx\x1b[0m\n```\n\n",
+    );
+}
+
+#[test]
+fn config_file() {
+    let path = std::env::temp_dir().join(format!("sprint-config-{}.toml", std::process::id()));
+    std::fs::write(
+        &path,
+        "# Theme\nfence = '~~~'\ninfo = \"sh\" # comment\nprompt = \"\\u0025 \"\n\
+        command_style = \"#ff0000+b\"\ndebounce = \"200ms\"\n",
+    )
+    .unwrap();
+
+    let sprint = |args: &[&str]| {
+        let output = std::process::Command::new(env!("CARGO_BIN_EXE_sprint"))
+            .args(args)
+            .env("SPRINT_CONFIG", &path)
+            .env_remove("SPRINT_SHELL")
+            .output()
+            .unwrap();
+        String::from_utf8(output.stdout).unwrap() + &String::from_utf8(output.stderr).unwrap()
+    };
+
+    let config = sprint(&["--print-config", "-i", "text"]);
+    assert!(config.starts_with(
+        "shell = \"sh -c\"\nfence = \"~~~\"\ninfo = \"text\"\nprompt = \"% \"\n\
+        debounce = \"200ms\"\n"
+    ));
+    assert!(config.contains("command_style = \"#ff0000+bold\"\n"));
+
+    // The printed config can be read back
+    std::fs::write(&path, &config).unwrap();
+    assert_eq!(sprint(&["--print-config", "-i", "text"]), config);
+
+    // The debounce may be a number of seconds
+    std::fs::write(&path, "debounce = 0.2\n").unwrap();
+    assert!(sprint(&["--print-config"]).contains("\ndebounce = \"200ms\"\n"));
+
+    std::fs::write(&path, "colour = \"red\"\n").unwrap();
+    assert!(sprint(&["--print-config"]).contains("unknown field `colour`"));
+
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn style_options() {
+    let sprint = |args: &[&str]| {
+        std::process::Command::new(env!("CARGO_BIN_EXE_sprint"))
+            .args(args)
+            .env("SPRINT_CONFIG", "/dev/null")
+            .output()
+            .unwrap()
+    };
+
+    let output = sprint(&[
+        "--print-config",
+        "--command-style",
+        "#ff0000+ul",
+        "--error-style",
+        "red",
+    ]);
+    let config = String::from_utf8(output.stdout).unwrap();
+    assert!(config.contains("command_style = \"#ff0000+underline\"\n"));
+    assert!(config.contains("error_style = \"red\"\n"));
+
+    let output = sprint(&["--fence-style", "bold+nope", "true"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("invalid value 'bold+nope' for '--fence-style <STYLE>': Invalid style spec"));
+}
+
+#[test]
+fn once() {
+    let sprint = |args: &[&str]| {
+        std::process::Command::new(env!("CARGO_BIN_EXE_sprint"))
+            .args(args)
+            .env("SPRINT_CONFIG", "/dev/null")
+            .stdin(std::process::Stdio::null())
+            .output()
+            .unwrap()
+    };
+
+    // Exits with the code of the first failed command instead of watching
+    let output = sprint(&["-w", "src", "--once", "-k", "true", "exit 3", "exit 4"]);
+    assert_eq!(output.status.code(), Some(3));
+
+    // Requires commands instead of running interactively
+    let output = sprint(&["-w", "src", "--once"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("the following required arguments were not provided"));
+}
+
+#[cfg(all(unix, feature = "pty"))]
+#[test]
+fn pty() {
+    let shell = Shell {
+        print: false,
+        ..Default::default()
+    };
+
+    let command = Command {
+        stdout: Pipe::string(),
+        stderr: Pipe::string(),
+        ..Command::new("test -t 1 && echo tty; echo err >&2")
+    };
+
+    let result = shell
+        .run1(&Command {
+            pty: true,
+            ..command.clone()
+        })
+        .unwrap();
+    assert_eq!(
+        result.stdout,
+        Pipe::String(Some(String::from("tty\nerr\n")))
+    );
+    assert_eq!(result.stderr, Pipe::String(Some(String::new())));
+
+    let result = shell.run1(&command).unwrap();
+    assert_eq!(result.stdout, Pipe::String(Some(String::new())));
+    assert_eq!(result.stderr, Pipe::String(Some(String::from("err\n"))));
+
+    let shell = Shell {
+        batch_timeout: Some(std::time::Duration::from_secs(1)),
+        ..shell
+    };
+
+    // Output is only read until the deadline if a background process holds the terminal open
+    let start = std::time::Instant::now();
+    let result = shell
+        .run(&[Command {
+            pty: true,
+            stdout: Pipe::string(),
+            ..Command::new("sleep 10 & echo hi")
+        }])
+        .remove(0);
+    assert!(start.elapsed() < std::time::Duration::from_secs(5));
+    assert_eq!(result.stdout, Pipe::String(Some(String::from("hi\n"))));
+
+    // The capture limit applies to the terminal's output
+    let start = std::time::Instant::now();
+    let result = shell
+        .run(&[Command {
+            pty: true,
+            stdout: Pipe::string(),
+            capture_limit: Some(10),
+            ..Command::new("yes")
+        }])
+        .remove(0);
+    assert!(start.elapsed() < std::time::Duration::from_secs(1));
+    assert_eq!(
+        result.stdout,
+        Pipe::String(Some(String::from("y\ny\ny\ny\ny\n")))
+    );
+    assert!(result.truncated);
+}
+
+#[test]
+fn run_channel() {
+    let shell = Shell {
+        print: false,
+        ..Default::default()
+    };
+
+    let chunks = shell
+        .run_channel(&Command::new("printf a; sleep 0.1; printf b"))
+        .unwrap()
+        .into_iter()
+        .collect::<Vec<_>>();
+
+    assert_eq!(
+        chunks,
+        vec![
+            OutputChunk::Stdout(b"a".to_vec()),
+            OutputChunk::Stdout(b"b".to_vec()),
+            OutputChunk::Exit(Some(0)),
+        ],
+    );
+
+    assert!(shell
+        .run_channel(&Command::exec(["/nonexistent/program"]))
+        .is_err());
+}
+
+#[test]
+fn empty_chrome() {
+    let shell = Shell {
+        fence: String::new(),
+        prompt: String::new(),
+        color: ColorOverride::Never,
+        ..Default::default()
+    };
+
+    assert_eq!(shell.render(&[Command::new("echo a")]), "echo a\na\n\n");
+
+    let shell = Shell {
+        info: String::new(),
+        prompt: String::new(),
+        fence_style: style("").unwrap(),
+        command_style: style("").unwrap(),
+        color: ColorOverride::Always,
+        ..Default::default()
+    };
+
+    // No escape codes for the empty info and prompt despite their styles
+    assert_eq!(
+        shell.render(&[Command::new("echo a")]),
+        "```\necho a\na\n```\n\n"
+    );
+}
+
+#[test]
+fn run_owned() {
+    let shell = Shell {
+        print: false,
+        ..Default::default()
+    };
+
+    let commands = vec![
+        Command {
+            stdin: Pipe::String(Some(String::from("a\nb\n"))),
+            stdout: Pipe::string(),
+            ..Command::new("wc -l")
+        },
+        Command {
+            stdin: Pipe::String(Some(String::from("c\n"))),
+            stdout: Pipe::string(),
+            ..Command::new("cat; exit 2")
+        },
+    ];
+
+    for sync in [true, false] {
+        let shell = Shell {
+            sync,
+            ..shell.clone()
+        };
+
+        let owned = commands.clone();
+        let pointers = owned.iter().map(|x| x.command.as_ptr()).collect::<Vec<_>>();
+        let results = shell.run_owned(owned);
+
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].stdout.clone().into_string().trim(), "2");
+        assert_eq!(results[0].stdin, commands[0].stdin);
+        assert_eq!(results[1].stdout, Pipe::String(Some(String::from("c\n"))));
+        assert_eq!(results[1].stdin, commands[1].stdin);
+        assert_eq!(results[1].code, Some(2));
+
+        // Commands are moved into their results
+        assert_eq!(
+            results
+                .iter()
+                .map(|x| x.command.as_ptr())
+                .collect::<Vec<_>>(),
+            pointers
+        );
+    }
+
+    // Also by Shell::core
+    let command = commands[1].clone();
+    let pointer = command.command.as_ptr();
+    let result = shell.core(command).unwrap();
+    assert_eq!(result.command.as_ptr(), pointer);
+    assert_eq!(result.code, Some(2));
+}
+
+#[test]
+fn style_error() {
+    assert_eq!(
+        style("red+#ff"),
+        Err(StyleError::WrongLength(String::from("ff")))
+    );
+    assert_eq!(
+        style("#-f0000"),
+        Err(StyleError::BadHex(String::from("-f0000")))
+    );
+    assert_eq!(
+        style("bright+red"),
+        Err(StyleError::InvalidToken(String::from("bright")))
+    );
+
+    // Converts to anyhow
+    fn parse(s: &str) -> anyhow::Result<()> {
+        style(s)?;
+        Ok(())
+    }
+    assert_eq!(
+        parse("#é0000").unwrap_err().to_string(),
+        "Invalid style spec color: \"é0000\" is not hexadecimal!",
+    );
+}
+
+#[test]
+fn style_gray() {
+    assert_eq!(style("gray:85").unwrap(), style("#555555").unwrap());
+    assert_eq!(
+        style("grey:0+on-gray:255+bold").unwrap(),
+        style("#000000+on-#ffffff+bold").unwrap(),
+    );
+    assert_eq!(style("on-grey:16").unwrap(), style("on-#101010").unwrap());
+    assert_eq!(
+        style("gray:300"),
+        Err(StyleError::OutOfRange(String::from("300")))
+    );
+    assert_eq!(
+        style("gray:-1"),
+        Err(StyleError::InvalidToken(String::from("gray:-1")))
+    );
+    assert_eq!(
+        style("gray:"),
+        Err(StyleError::InvalidToken(String::from("gray:")))
+    );
+}
+
+#[test]
+fn run_sections() {
+    let buffer = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    let shell = Shell {
+        out: Some(Writer(buffer.clone())),
+        color: ColorOverride::Never,
+        ..Default::default()
+    };
+
+    let sections = [
+        (String::from("Setup"), vec![Command::new("echo a")]),
+        (String::from("Build"), vec![Command::new("exit 2")]),
+        (String::from("Test"), vec![Command::new("echo c")]),
+    ];
+
+    let results = shell.run_sections(&sections);
+
+    assert_eq!(results.len(), 2);
+    assert_eq!(results[0][0].code, Some(0));
+    assert_eq!(results[1][0].code, Some(2));
+    assert_eq!(
+        String::from_utf8(buffer.lock().unwrap().clone()).unwrap(),
+        "\
+## Setup
+
+```text
+$ echo a
+a
+```
+
+## Build
+
+```text
+$ exit 2
+```
+
+**Command `exit 2` exited with code: `2`!**
+
+",
+    );
+
+    let results = Shell {
+        keep_going: true,
+        print: false,
+        ..Default::default()
+    }
+    .run_sections(&sections);
+
+    assert_eq!(results.len(), 3);
+}
+
+#[test]
+fn open_close_fence() {
+    let buffer = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    let shell = Shell {
+        out: Some(Writer(buffer.clone())),
+        color: ColorOverride::Never,
+        ..Default::default()
+    };
+
+    shell.open_fence();
+    shell.emitln(format_args!("rust"));
+    shell.close_fence();
+
+    assert_eq!(
+        String::from_utf8(buffer.lock().unwrap().clone()).unwrap(),
+        "```rust\n```\n\n",
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn shell_argv() {
+    let dir = std::env::temp_dir().join(format!("sprint shell argv {}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let sh = dir.join("sh");
+    std::os::unix::fs::symlink("/bin/sh", &sh).unwrap();
+    let sh = sh.to_str().unwrap().to_string();
+
+    let shell = Shell {
+        shell_argv: Some(vec![sh.clone(), String::from("-c")]),
+        print: false,
+        ..Default::default()
+    };
+
+    assert_eq!(
+        shell.resolve(&Command::new("echo $0")).unwrap(),
+        (
+            sh.clone(),
+            vec![String::from("-c"), String::from("echo $0")]
+        ),
+    );
+    assert_eq!(shell.pipe1("echo $0"), format!("{sh}\n"));
+
+    // A command's shell override takes precedence
+    assert_eq!(
+        shell
+            .resolve(&Command {
+                shell: Some(None),
+                ..Command::new("echo a")
+            })
+            .unwrap(),
+        (String::from("echo"), vec![String::from("a")]),
+    );
+
+    assert!(Shell {
+        shell_argv: Some(vec![]),
+        ..shell
+    }
+    .resolve(&Command::new("true"))
+    .is_err());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn wrap_quoted() {
+    let shell = Shell {
+        color: ColorOverride::Never,
+        dry_run: true,
+        wrap_operators: true,
+        ..Default::default()
+    };
+
+    assert_eq!(
+        shell.render(&[Command::new(
+            "awk '{print; next}' a | sed \"s/a | b/c/; s/x && y/z/\" && echo a\\; b; true"
+        )]),
+        "\
+```text
+awk '{print; next}' a \\
+| sed \"s/a | b/c/; s/x && y/z/\" \\
+&& echo a\\; b; \\
+true
+```
+
+",
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn signal() {
+    let shell = Shell {
+        color: ColorOverride::Never,
+        ..Default::default()
+    };
+
+    let result = shell.run1(&Command::new("kill -SEGV $$")).unwrap();
+    assert_eq!(result.code, None);
+    assert_eq!(result.signal, Some(11));
+
+    assert_eq!(
+        shell.render(&[Command::new("kill -TERM $$")]),
+        "\
+```text
+$ kill -TERM $$
+```
+
+**Command `kill -TERM $$` was killed by SIGTERM!**
+
+",
+    );
+
+    let result = shell.run1(&Command::new("exit 3")).unwrap();
+    assert_eq!(result.signal, None);
+}
+
+#[test]
+fn run_stdin_lines() {
+    let shell = Shell {
+        print: false,
+        ..Default::default()
+    };
+
+    // More than a pipe buffer in both directions
+    let result = shell.run_stdin_lines("cat", (0..100_000).map(|i| i.to_string()));
+    assert_eq!(result.code, Some(0));
+    assert_eq!(result.stdout.into_string().lines().count(), 100_000);
+
+    // Stops writing an endless iterator once the command exits
+    let result = shell.run_stdin_lines("head -n 2", (0..).map(|i| format!("line {i}")));
+    assert_eq!(
+        result.stdout,
+        Pipe::String(Some(String::from("line 0\nline 1\n")))
+    );
+}
+
+#[test]
+fn tail() {
+    let shell = Shell {
+        print: false,
+        ..Default::default()
+    };
+
+    let command = Command {
+        stdout: Pipe::string(),
+        stderr: Pipe::string(),
+        tail: Some(2),
+        ..Command::new("seq 100000; echo a >&2; printf 'b\\nc'>&2; exit 1")
+    };
+
+    let result = shell.run1(&command).unwrap();
+    assert_eq!(
+        result.stdout,
+        Pipe::String(Some(String::from("99999\n100000\n")))
+    );
+    assert_eq!(result.stderr, Pipe::String(Some(String::from("b\nc"))));
+    assert_eq!(result.code, Some(1));
+    assert!(!result.truncated);
+
+    // With a deadline
+    let result = Shell {
+        batch_timeout: Some(std::time::Duration::from_secs(10)),
+        ..shell
+    }
+    .run(&[command])
+    .remove(0);
+    assert_eq!(
+        result.stdout,
+        Pipe::String(Some(String::from("99999\n100000\n")))
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn persistent_shell() {
+    let shell = Shell {
+        print: false,
+        ..Default::default()
+    };
+
+    let mut persistent = PersistentShell::new(&shell).unwrap();
+
+    // State persists between commands
+    let results = persistent.run(&[
+        Command::new("cd /tmp; f() { echo \"f $1\"; }"),
+        Command {
+            stdout: Pipe::string(),
+            stderr: Pipe::string(),
+            ..Command::new("pwd; f x; echo err >&2; false")
+        },
+    ]);
+    assert_eq!(results.len(), 2);
+    assert_eq!(results[1].code, Some(1));
+    assert_eq!(
+        results[1].stdout,
+        Pipe::String(Some(String::from("/tmp\nf x\n")))
+    );
+    assert_eq!(results[1].stderr, Pipe::String(Some(String::from("err\n"))));
+
+    // Output without a trailing newline
+    let result = persistent
+        .run1(&Command {
+            stdout: Pipe::string(),
+            ..Command::new("printf abc")
+        })
+        .unwrap();
+    assert_eq!(result.stdout, Pipe::String(Some(String::from("abc"))));
+    assert_eq!(result.code, Some(0));
+
+    // Syntax errors fail the command without stopping the shell
+    for command in ["echo \"it's", "if true", "echo '"] {
+        let result = persistent
+            .run1(&Command {
+                stderr: Pipe::Null,
+                ..Command::new(command)
+            })
+            .unwrap();
+        assert_eq!(result.code, Some(2));
+    }
+    let result = persistent
+        .run1(&Command {
+            stdout: Pipe::string(),
+            ..Command::new("echo \"it's\" 'a' \\'; pwd")
+        })
+        .unwrap();
+    assert_eq!(
+        result.stdout,
+        Pipe::String(Some(String::from("it's a '\n/tmp\n")))
+    );
+
+    // The shell exiting is an error
+    assert!(persistent.run1(&Command::new("exit 3")).is_err());
+}
+
+#[test]
+fn check() {
+    let shell = Shell::default();
+
+    assert_eq!(shell.check("printf ok").unwrap(), "ok");
+
+    // No stderr
+    let e = shell.check("exit 3").unwrap_err();
+    assert_eq!(e.to_string(), "Command `exit 3` exited with code: `3`!");
+
+    // More than a pipe buffer of stderr
+    let e = shell
+        .check("head -c 200000 /dev/zero | tr '\\0' x >&2; exit 1")
+        .unwrap_err();
+    assert_eq!(
+        e.to_string(),
+        format!(
+            "Command `head -c 200000 /dev/zero | tr '\\0' x >&2; exit 1` exited with code: \
+            `1`!\n{}",
+            "x".repeat(200_000)
+        )
+    );
+
+    // Never prints and returns nothing in dry run
+    let shell = Shell {
+        dry_run: true,
+        ..Default::default()
+    };
+    assert_eq!(shell.check("exit 1").unwrap(), "");
+}
+
+#[test]
+fn env_keep() {
+    // Set variables in a child process instead of mutating this process' environment
+    if std::env::var_os("SPRINT_TEST_CHILD").is_none() {
+        child(
+            "env_keep",
+            &[("SPRINT_ENV_KEEP", "kept"), ("SPRINT_ENV_DROP", "dropped")],
+        );
+        return;
+    }
+
+    let shell = Shell {
+        print: false,
+        env_clear: true,
+        env_keep: vec![
+            String::from("PATH"),
+            String::from("SPRINT_ENV_KEEP"),
+            String::from("SPRINT_ENV_UNSET"),
+        ],
+        ..Default::default()
+    };
+
+    let output = shell
+        .capture("echo \"$SPRINT_ENV_KEEP-$SPRINT_ENV_DROP-${SPRINT_ENV_UNSET-unset}\"")
+        .unwrap();
+    assert_eq!(output.stdout, "kept--unset\n");
+
+    // Nothing is removed without env_clear
+    let shell = Shell {
+        env_clear: false,
+        ..shell
+    };
+    let output = shell.capture("echo \"$SPRINT_ENV_DROP\"").unwrap();
+    assert_eq!(output.stdout, "dropped\n");
+}
+
+#[test]
+fn try_run_str() {
+    let shell = Shell {
+        shell: Some(String::from("not-a-real-shell -c")),
+        print: false,
+        ..Default::default()
+    };
+    let e = shell.try_run_str(&["echo hello"]).unwrap_err();
+    assert_eq!(
+        e.to_string(),
+        "Shell program `not-a-real-shell` not found; is it installed?"
+    );
+
+    // Nothing runs if any command cannot be resolved
+    let marker = std::env::temp_dir().join(format!("sprint-try-run-str-{}", std::process::id()));
+    let shell = Shell {
+        shell: None,
+        print: false,
+        ..Default::default()
+    };
+    let touch = format!("touch {}", marker.display());
+    assert!(shell.try_run_str(&[&touch, "echo 'a"]).is_err());
+    assert!(!marker.exists());
+
+    let results = shell.try_run_str(&[&touch, "", "false"]).unwrap();
+    assert_eq!(results.len(), 3);
+    assert!(marker.exists());
+    assert_eq!(results[2].code, Some(1));
+    std::fs::remove_file(&marker).unwrap();
+}
+
+/// Run a test again in a child process with `SPRINT_TEST_CHILD` set and return its stdout and
+/// stderr
+fn child(test: &str, env: &[(&str, &str)]) -> (String, String) {
+    let output = std::process::Command::new(std::env::current_exe().unwrap())
+        .args(["--exact", test, "--nocapture"])
+        .env("SPRINT_TEST_CHILD", "1")
+        .envs(env.iter().copied())
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    (
+        String::from_utf8(output.stdout).unwrap(),
+        String::from_utf8(output.stderr).unwrap(),
+    )
+}
+
+#[test]
+fn decorations_to_stderr() {
+    if std::env::var_os("SPRINT_TEST_CHILD").is_some() {
+        // Start the command output on its own line after the test harness output
+        println!();
+        Shell {
+            decorations_to_stderr: true,
+            fence_empty: false,
+            output_prefix: Some(String::from("> ")),
+            color: ColorOverride::Never,
+            ..Default::default()
+        }
+        .run(&[Command::new("echo out"), Command::new("echo err >&2")]);
+        return;
+    }
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_sprint"))
+        .args(["--decorations-to-stderr", "-C", "never"])
+        .args(["echo out", "echo err >&2"])
+        .env("SPRINT_CONFIG", "/dev/null")
+        .env_remove("SPRINT_SHELL")
+        .output()
+        .unwrap();
+
+    // The commands' stdout stays clean on stdout
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "out\n");
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "\
+```text
+$ echo out
+
+$ echo err >&2
+err
+```
+
+"
+    );
+
+    // Prefixed output goes to the stream it came from
+    let (stdout, stderr) = child("decorations_to_stderr", &[]);
+    assert!(stdout.lines().any(|x| x == "> out"));
+    assert!(!stdout.contains("```") && !stdout.contains("echo"));
+    assert_eq!(
+        stderr,
+        "\
+```text
+$ echo out
+
+$ echo err >&2
+> err
+```
+
+"
+    );
+}